@@ -0,0 +1,61 @@
+use bencher::{benchmark_group, benchmark_main, black_box, Bencher};
+use convext::core::prelude::*;
+use itertools::Itertools;
+use rand::{prelude::StdRng, SeedableRng};
+
+fn examples() -> Vec<(Grammar, CompiledGrammar)> {
+    EXAMPLES
+        .iter()
+        .map(|(name, text)| {
+            let grammar = parse(text).expect(name);
+            let compiled = grammar.compile().expect(name);
+            (grammar, compiled)
+        })
+        .collect_vec()
+}
+
+fn expand_examples(bench: &mut Bencher) {
+    let examples = examples();
+    let settings = ExpandSettings::default();
+    bench.iter(|| {
+        for (_, compiled) in examples.iter() {
+            let mut rng = StdRng::seed_from_u64(100);
+            black_box(compiled.expand(&settings, &mut rng));
+        }
+    });
+}
+
+//Evaluate every property expression in the examples by walking the expression tree
+fn evaluate_tree(bench: &mut Bencher) {
+    let examples = examples();
+    let context = NodeProperties::default_initial();
+    let mut rng = StdRng::seed_from_u64(100);
+    bench.iter(|| {
+        for (grammar, _) in examples.iter() {
+            let invocations = grammar
+                .top_level
+                .iter()
+                .chain(grammar.rules.values().flat_map(|r| r.cases.iter().flat_map(|c| c.invocations.iter())));
+            for property in invocations.flat_map(|i| i.properties.iter()) {
+                black_box(property.value.try_get_value(grammar, &context, &mut rng).unwrap());
+            }
+        }
+    });
+}
+
+//Evaluate every property expression in the examples using the compiled bytecode
+fn evaluate_compiled(bench: &mut Bencher) {
+    let examples = examples();
+    let context = NodeProperties::default_initial();
+    let mut rng = StdRng::seed_from_u64(100);
+    bench.iter(|| {
+        for (_, compiled) in examples.iter() {
+            for property in compiled.invocations.iter().flat_map(|i| i.properties.iter()) {
+                black_box(property.value.evaluate(&compiled.variables, &context, &mut rng));
+            }
+        }
+    });
+}
+
+benchmark_group!(benches, expand_examples, evaluate_tree, evaluate_compiled);
+benchmark_main!(benches);
@@ -0,0 +1,149 @@
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use bencher::{benchmark_group, black_box, run_tests_console, Bencher, TestOpts};
+use convext::core::prelude::*;
+use rand::{prelude::StdRng, SeedableRng};
+
+//Counts the bytes allocated and not yet freed, so the memory held by a tree can be measured
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+//A binary tree with an expression on every invocation
+const TREE: &str = "branch
+rul branch
+square p0.1 h?d v0.5 a0.9
+branch p0.7 x0.5 y0.5 r45 h10 add ?d
+branch p0.7 xsub0.5 y0.5 r sub 45 h10 add ?d
+end";
+
+fn settings(max_nodes: usize) -> ExpandSettings {
+    ExpandSettings {
+        max_nodes,
+        max_depth: 100,
+        min_a: 0.0,
+        min_p: 0.0,
+        cull_margin: f32::INFINITY,
+        ..Default::default()
+    }
+}
+
+//A node as nodes were stored before the arena, owning a clone of its invocation and its children
+#[allow(dead_code)]
+struct NestedNode {
+    invocation: Option<Invocation>,
+    absolute_properties: NodeProperties,
+    children: Option<Vec<NestedNode>>,
+}
+
+fn nest(tree: &NodeTree, index: usize, invocations: &[Option<&Invocation>]) -> NestedNode {
+    let node = &tree.nodes[index];
+    NestedNode {
+        invocation: invocations[node.invocation.0].cloned(),
+        absolute_properties: node.absolute_properties.clone(),
+        children: node
+            .children
+            .clone()
+            .map(|children| children.map(|c| nest(tree, c, invocations)).collect()),
+    }
+}
+
+//The bytes still allocated after making a value, while it is alive
+fn held_bytes<T>(make: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATED.load(Ordering::Relaxed);
+    let value = make();
+    (value, ALLOCATED.load(Ordering::Relaxed) - before)
+}
+
+//Print the memory held by the arena and by the nested nodes it replaced
+fn report_memory(max_nodes: usize) {
+    let grammar = parse(TREE).unwrap();
+    let compiled = grammar.compile().unwrap();
+    //Invocations are numbered in the order they are compiled, after the root
+    let invocations = std::iter::once(None)
+        .chain(
+            grammar
+                .top_level
+                .iter()
+                .chain(grammar.rules.values().flat_map(|r| r.cases.iter().flat_map(|c| c.invocations.iter())))
+                .map(Some),
+        )
+        .collect::<Vec<_>>();
+    assert_eq!(invocations.len(), compiled.invocations.len());
+
+    let mut rng = StdRng::seed_from_u64(100);
+    let (tree, arena) = held_bytes(|| compiled.expand(&settings(max_nodes), &mut rng));
+    let (_, nested) = held_bytes(|| nest(&tree, NodeTree::ROOT, &invocations));
+    println!(
+        "{} nodes: arena {} KiB, nested nodes with cloned invocations {} KiB",
+        tree.nodes.len(),
+        arena / 1024,
+        nested / 1024
+    );
+}
+
+fn expand(bench: &mut Bencher, max_nodes: usize) {
+    let grammar = parse(TREE).unwrap().compile().unwrap();
+    let settings = settings(max_nodes);
+    bench.iter(|| {
+        let mut rng = StdRng::seed_from_u64(100);
+        black_box(grammar.expand(&settings, &mut rng))
+    });
+}
+
+//A variable slider moving, when only the top level circle reads the variable.
+//It is the tree of `expand_10k` with one more circle, so the two show how much the cache saves.
+fn expand_cached_10k(bench: &mut Bencher) {
+    let mut grammar = parse(&format!("let hue 0\ncircle p0.1 h?hue\n{TREE}"))
+        .unwrap()
+        .compile()
+        .unwrap();
+    let settings = settings(10_000);
+    let mut cache = ExpansionCache::default();
+    let mut hue = 0.0;
+    bench.iter(|| {
+        hue = (hue + 1.0) % 360.0;
+        grammar.set_variable("hue", hue);
+        let mut rng = StdRng::seed_from_u64(100);
+        black_box(grammar.expand_cached(&settings, &mut rng, &mut cache).nodes.len())
+    });
+}
+
+fn expand_10k(bench: &mut Bencher) {
+    expand(bench, 10_000)
+}
+
+fn expand_100k(bench: &mut Bencher) {
+    expand(bench, 100_000)
+}
+
+benchmark_group!(benches, expand_10k, expand_cached_10k, expand_100k);
+
+fn main() {
+    report_memory(10_000);
+    report_memory(100_000);
+
+    let mut test_opts = TestOpts::default();
+    if let Some(arg) = std::env::args().skip(1).find(|arg| *arg != "--bench") {
+        test_opts.filter = Some(arg);
+    }
+    run_tests_console(&test_opts, benches()).unwrap();
+}
@@ -29,22 +29,119 @@ pub enum BinaryOperator {
 
 impl BinaryOperator {
 
-    pub fn apply_range(self, left: ValueOrRange, right: ValueOrRange)-> ValueOrRange{
-        match left {
-            ValueOrRange::Value(l) => match right{
-                ValueOrRange::Value(r) => ValueOrRange::Value(self.apply(l, r)),
-                ValueOrRange::Range { start, end } => 
-                ValueOrRange::Range { start: self.apply(l, start), end: self.apply(l, end) },
-            },
-            ValueOrRange::Range { start, end } =>
-            match right {
-                ValueOrRange::Value(r) => 
-                ValueOrRange::Range { start: self.apply( start, r), end: self.apply(end, r) },
-                
-                //TODO this should really create a compound range
-                ValueOrRange::Range { start:start2, end:end2 } => 
-                ValueOrRange::Range { start: self.apply( start, start2), end: self.apply(end,end2) },
-            },
+    ///Apply this operator to every pair of values from the two ranges.
+    ///Comparisons which could be either true or false give both 0 and 1.
+    pub fn apply_range(self, left: ValueOrRange, right: ValueOrRange) -> ValueOrRange {
+        if let (ValueOrRange::Value(l), ValueOrRange::Value(r)) = (left, right) {
+            return ValueOrRange::Value(self.apply(l, r));
+        }
+
+        let left_intervals = left.intervals();
+        let right_intervals = right.intervals();
+
+        match self {
+            BinaryOperator::Add | BinaryOperator::Sub | BinaryOperator::Mul | BinaryOperator::Div => {
+                ValueOrRange::from_intervals(
+                    left_intervals
+                        .iter()
+                        .cartesian_product(right_intervals.iter())
+                        .flat_map(|(l, r)| self.apply_interval(*l, *r)),
+                )
+            }
+            BinaryOperator::And | BinaryOperator::Or => {
+                let truthiness = |intervals: &[Interval]| {
+                    let can_be_zero = intervals.iter().any(|i| i.contains(0.0));
+                    let can_be_nonzero = intervals.iter().any(|i| i.start != 0.0 || i.end != 0.0);
+                    (can_be_nonzero, can_be_zero)
+                };
+                let (left_true, left_false) = truthiness(&left_intervals);
+                let (right_true, right_false) = truthiness(&right_intervals);
+
+                if self == BinaryOperator::And {
+                    Self::truth_value(left_true && right_true, left_false || right_false)
+                } else {
+                    Self::truth_value(left_true || right_true, left_false && right_false)
+                }
+            }
+            _ => {
+                let (mut can_be_true, mut can_be_false) = (false, false);
+                for (l, r) in left_intervals.iter().cartesian_product(right_intervals.iter()) {
+                    let (t, f) = self.compare_interval(*l, *r);
+                    can_be_true |= t;
+                    can_be_false |= f;
+                }
+                Self::truth_value(can_be_true, can_be_false)
+            }
+        }
+    }
+
+    ///The intervals containing every result of applying this arithmetic operator to values from the intervals
+    fn apply_interval(self, left: Interval, right: Interval) -> Vec<Interval> {
+        //Zero times infinity is zero, as the infinity stands in for a large finite value
+        let mul = |a: f32, b: f32| if a == 0.0 || b == 0.0 { 0.0 } else { a * b };
+        let product = |l: Interval, r: Interval| {
+            let products = [
+                mul(l.start, r.start),
+                mul(l.start, r.end),
+                mul(l.end, r.start),
+                mul(l.end, r.end),
+            ];
+            Interval::new(
+                products.iter().cloned().fold(f32::INFINITY, f32::min),
+                products.iter().cloned().fold(f32::NEG_INFINITY, f32::max),
+            )
+        };
+
+        match self {
+            BinaryOperator::Add => vec![Interval::new(left.start + right.start, left.end + right.end)],
+            BinaryOperator::Sub => vec![Interval::new(left.start - right.end, left.end - right.start)],
+            BinaryOperator::Mul => vec![product(left, right)],
+            BinaryOperator::Div => {
+                if !right.contains(0.0) {
+                    vec![product(left, Interval::new(1.0 / right.start, 1.0 / right.end))]
+                } else if right.start == 0.0 && right.end == 0.0 {
+                    [left.start, left.end].iter().map(|l| Interval::point(l / 0.0)).collect_vec()
+                } else {
+                    //Dividing by values either side of zero gives results beyond any bound in both directions
+                    let mut reciprocals = vec![];
+                    if right.start < 0.0 {
+                        reciprocals.push(Interval::new(f32::NEG_INFINITY, 1.0 / right.start));
+                    }
+                    if right.end > 0.0 {
+                        reciprocals.push(Interval::new(1.0 / right.end, f32::INFINITY));
+                    }
+                    reciprocals.into_iter().map(|r| product(left, r)).collect_vec()
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    ///Whether this comparison could be true, and whether it could be false, for values from the intervals
+    fn compare_interval(self, left: Interval, right: Interval) -> (bool, bool) {
+        match self {
+            BinaryOperator::Eq | BinaryOperator::Neq => {
+                let can_be_equal = left.start <= right.end && right.start <= left.end;
+                let can_be_unequal = !(left.start == left.end && left == right);
+                if self == BinaryOperator::Eq {
+                    (can_be_equal, can_be_unequal)
+                } else {
+                    (can_be_unequal, can_be_equal)
+                }
+            }
+            BinaryOperator::Lt => (left.start < right.end, left.end >= right.start),
+            BinaryOperator::Gt => (left.end > right.start, left.start <= right.end),
+            BinaryOperator::LEq => (left.start <= right.end, left.end > right.start),
+            BinaryOperator::GEq => (left.end >= right.start, left.start < right.end),
+            _ => unreachable!(),
+        }
+    }
+
+    fn truth_value(can_be_true: bool, can_be_false: bool) -> ValueOrRange {
+        match (can_be_true, can_be_false) {
+            (true, false) => ValueOrRange::Value(1.0),
+            (false, true) => ValueOrRange::Value(0.0),
+            _ => ValueOrRange::Value(0.0).union(ValueOrRange::Value(1.0)),
         }
     }
 
@@ -0,0 +1,241 @@
+use crate::core::prelude::*;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+///An axis aligned rectangle in absolute coordinates
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Bounds {
+    pub min_x: f32,
+    pub min_y: f32,
+    pub max_x: f32,
+    pub max_y: f32,
+}
+
+impl Bounds {
+    ///The area shown by the svg viewbox
+    pub const CANVAS: Bounds = Bounds {
+        min_x: -1.0,
+        min_y: -1.0,
+        max_x: 1.0,
+        max_y: 1.0,
+    };
+
+    ///Bounds which contain everything
+    pub const INFINITE: Bounds = Bounds {
+        min_x: f32::NEG_INFINITY,
+        min_y: f32::NEG_INFINITY,
+        max_x: f32::INFINITY,
+        max_y: f32::INFINITY,
+    };
+
+    pub fn new(min_x: f32, min_y: f32, max_x: f32, max_y: f32) -> Self {
+        Self {
+            min_x,
+            min_y,
+            max_x,
+            max_y,
+        }
+    }
+
+    ///The bounds of a rectangle around a centre, which may be a range
+    pub fn around(x: ValueOrRange, y: ValueOrRange, half_width: f32, half_height: f32) -> Self {
+        Self {
+            min_x: x.min_value() - half_width,
+            min_y: y.min_value() - half_height,
+            max_x: x.max_value() + half_width,
+            max_y: y.max_value() + half_height,
+        }
+    }
+
+    ///These bounds, grown by the margin on every side
+    pub fn grow(self, margin: f32) -> Self {
+        Self {
+            min_x: self.min_x - margin,
+            min_y: self.min_y - margin,
+            max_x: self.max_x + margin,
+            max_y: self.max_y + margin,
+        }
+    }
+
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.min_x <= other.max_x
+            && other.min_x <= self.max_x
+            && self.min_y <= other.max_y
+            && other.min_y <= self.max_y
+    }
+
+    pub fn contains(&self, other: &Self) -> bool {
+        self.min_x <= other.min_x
+            && self.min_y <= other.min_y
+            && other.max_x <= self.max_x
+            && other.max_y <= self.max_y
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            min_x: self.min_x.min(other.min_x),
+            min_y: self.min_y.min(other.min_y),
+            max_x: self.max_x.max(other.max_x),
+            max_y: self.max_y.max(other.max_y),
+        }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.max_x - self.min_x
+    }
+
+    pub fn height(&self) -> f32 {
+        self.max_y - self.min_y
+    }
+
+    pub fn centre(&self) -> (f32, f32) {
+        ((self.min_x + self.max_x) / 2.0, (self.min_y + self.max_y) / 2.0)
+    }
+
+    ///How much these bounds must be scaled by to fit inside a rectangle of this size
+    pub fn scale_to_fit(&self, width: f32, height: f32) -> f32 {
+        (width / self.width()).min(height / self.height())
+    }
+}
+
+impl Primitive {
+    ///The furthest any point of this shape is from its centre, when its half width and half height are 1
+    pub fn unit_radius(&self) -> f32 {
+        match self {
+            Primitive::Circle | Primitive::Polygon(_) => 1.0,
+            Primitive::Square | Primitive::RightTriangle => std::f32::consts::SQRT_2,
+        }
+    }
+
+    ///A conservative bounding box for this shape when drawn with these absolute properties
+    pub fn get_bounds(&self, properties: &NodeProperties) -> Bounds {
+        let p = properties.p.max_value().max(0.0);
+        let half_width = p * properties.w.max_abs();
+        let half_height = p * properties.l.max_abs();
+
+        match properties.r {
+            ValueOrRange::Value(r) => {
+                let (sin, cos) = r.to_radians().sin_cos();
+                let (sin, cos) = (sin.abs(), cos.abs());
+                let (extent_x, extent_y) = match self {
+                    Primitive::Circle => (
+                        (half_width * cos).hypot(half_height * sin),
+                        (half_width * sin).hypot(half_height * cos),
+                    ),
+                    //Every other shape fits in its rectangle
+                    _ => (
+                        half_width * cos + half_height * sin,
+                        half_width * sin + half_height * cos,
+                    ),
+                };
+                Bounds::around(properties.x, properties.y, extent_x, extent_y)
+            }
+            ValueOrRange::Range { .. } | ValueOrRange::Compound(_) => {
+                let radius = self.unit_radius() * half_width.max(half_height);
+                Bounds::around(properties.x, properties.y, radius, radius)
+            }
+        }
+    }
+}
+
+impl CompiledGrammar {
+    ///A conservative bounding box for everything a node and its descendants could draw
+    pub fn get_bounds(&self, node: &Node) -> Bounds {
+        let properties = &node.absolute_properties;
+        match self.get_invocation(node.invocation).method {
+            CompiledMethod::Primitive(primitive) => primitive.get_bounds(properties),
+            CompiledMethod::Rule(rule) => {
+                let extent = self.rule_extents[rule.0];
+                if !extent.is_finite() {
+                    return Bounds::INFINITE;
+                }
+                let scale = properties.p.max_value().max(0.0)
+                    * properties.w.max_abs().max(properties.l.max_abs()).max(1.0);
+                Bounds::around(properties.x, properties.y, scale * extent, scale * extent)
+            }
+            CompiledMethod::Root => Bounds::INFINITE,
+        }
+    }
+
+    ///How far the offset of an invocation can be from its parent's centre, and how much it can scale its subtree by.
+    ///Returns `None` if this cannot be known without a node.
+    fn invocation_reach(&self, id: InvocationId) -> Option<(f32, f32)> {
+        let invocation = self.get_invocation(id);
+        //Wallpaper covers the whole canvas. Other symmetries keep every copy as far from the parent's centre as the original
+        if matches!(&invocation.symmetry, Some(s) if s.is_wallpaper()) {
+            return None;
+        }
+        let mut properties = NodeProperties::default_additive();
+        for prop in invocation.properties.iter() {
+            match prop.key {
+                PropertyKey::X | PropertyKey::Y | PropertyKey::P | PropertyKey::W | PropertyKey::L => {
+                    prop.key.set(&mut properties, prop.value.evaluate_static(&self.variables)?)
+                }
+                _ => {}
+            }
+        }
+
+        let offset = properties.x.max_abs().hypot(properties.y.max_abs());
+        let scale = properties.p.max_value().max(0.0)
+            * properties.w.max_abs().max(properties.l.max_abs()).max(1.0);
+        Some((offset, scale))
+    }
+
+    ///For each rule, a bound on how far anything drawn by its subtree can be from the centre of a node with `p`, `w` and `l` of 1.
+    ///This is the least solution of `extent = max(offset + scale * child extent)` over every invocation the rule can make.
+    ///It is infinite if no bound could be found, for example because a recursive rule does not shrink.
+    pub fn compute_rule_extents(&self) -> Vec<f32> {
+        let reaches = (0..self.invocations.len())
+            .map(|i| self.invocation_reach(InvocationId(i)))
+            .collect_vec();
+
+        let step = |extents: &[f32]| -> Vec<f32> {
+            self.rules
+                .iter()
+                .map(|rule| {
+                    rule.cases
+                        .iter()
+                        .flat_map(|c| c.invocations.iter())
+                        .map(|id| match reaches[id.0] {
+                            Some((offset, scale)) => {
+                                let child_extent = match self.get_invocation(*id).method {
+                                    CompiledMethod::Primitive(p) => p.unit_radius(),
+                                    CompiledMethod::Rule(r) => extents[r.0],
+                                    CompiledMethod::Root => 0.0,
+                                };
+                                if scale == 0.0 {
+                                    offset
+                                } else {
+                                    offset + scale * child_extent
+                                }
+                            }
+                            None => f32::INFINITY,
+                        })
+                        .fold(0.0, f32::max)
+                })
+                .collect_vec()
+        };
+
+        //Approach the solution from below
+        let mut extents = vec![0.0; self.rules.len()];
+        for _ in 0..64 {
+            let next = step(&extents);
+            if next == extents {
+                break;
+            }
+            extents = next;
+        }
+
+        //Any extents which are not increased by a step bound the solution from above
+        let mut factor = 1.0;
+        for _ in 0..16 {
+            let candidate = extents.iter().map(|e| e * factor + 0.001).collect_vec();
+            if step(&candidate).iter().zip(candidate.iter()).all(|(s, c)| s <= c) {
+                return candidate;
+            }
+            factor *= 2.0;
+        }
+
+        vec![f32::INFINITY; self.rules.len()]
+    }
+}
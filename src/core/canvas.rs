@@ -0,0 +1,59 @@
+use crate::core::prelude::*;
+use serde::{Deserialize, Serialize};
+
+///A shape to fill on a 2d canvas, such as a html canvas
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct CanvasShape {
+    pub primitive: Primitive,
+    ///The transform from the unit shape, whose centre is at the origin and whose half width and half height are 1, to the canvas.
+    ///These are the `a b c d e f` of `setTransform`.
+    pub transform: [f32; 6],
+    ///The radii of the rounded corners of the unit shape
+    pub corner: (f32, f32),
+    pub color: Rgba,
+}
+
+impl Renderer for Vec<CanvasShape> {
+    type Output = Self;
+
+    fn draw_primitive(&mut self, shape: &RenderShape) {
+        let transform = &shape.transform;
+        let (half_width, half_height) = (transform.half_width, transform.half_height);
+        let is_drawn = [half_width, half_height, transform.x, transform.y]
+            .iter()
+            .all(|v| v.is_finite());
+        if shape.color.a <= 0.0 || half_width == 0.0 || half_height == 0.0 || !is_drawn {
+            return;
+        }
+
+        //Shapes rotate about their own centre
+        let (sin, cos) = transform.rotation.to_radians().sin_cos();
+        //Each radius is clamped to the half size along its own axis, as svg clamps `rx` and `ry`
+        let corner_x = transform.corner.min(half_width.abs());
+        let corner_y = transform.corner.min(half_height.abs());
+        self.push(CanvasShape {
+            primitive: shape.primitive,
+            transform: [
+                half_width * cos,
+                half_width * sin,
+                -half_height * sin,
+                half_height * cos,
+                transform.x,
+                transform.y,
+            ],
+            corner: (corner_x / half_width.abs(), corner_y / half_height.abs()),
+            color: shape.color,
+        });
+    }
+
+    fn finish(self) -> Self::Output {
+        self
+    }
+}
+
+impl NodeTree {
+    ///Every visible shape of this tree, in the order they are drawn
+    pub fn to_canvas_shapes(&self, grammar: &CompiledGrammar) -> Vec<CanvasShape> {
+        self.render(grammar, vec![])
+    }
+}
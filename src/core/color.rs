@@ -0,0 +1,53 @@
+use crate::core::prelude::*;
+use serde::{Deserialize, Serialize};
+
+///A colour with 8 bit red, green and blue channels and an opacity between 0 and 1
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: f32,
+}
+
+impl Rgba {
+    ///Convert from hue in degrees and saturation, lightness and opacity between 0 and 1
+    pub fn from_hsla(hue: f32, saturation: f32, lightness: f32, alpha: f32) -> Self {
+        let saturation = saturation.clamp(0.0, 1.0);
+        let lightness = lightness.clamp(0.0, 1.0);
+        let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+
+        let channel = |n: f32| {
+            let k = (n + hue.rem_euclid(360.0) / 30.0) % 12.0;
+            let v = lightness - chroma / 2.0 * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0);
+            (v * 255.0).round().clamp(0.0, 255.0) as u8
+        };
+
+        Self {
+            r: channel(0.0),
+            g: channel(8.0),
+            b: channel(4.0),
+            a: alpha.clamp(0.0, 1.0),
+        }
+    }
+
+    ///The colour a shape with these absolute properties is filled with
+    pub fn from_properties(properties: &NodeProperties) -> Self {
+        Self::from_hsla(
+            properties.h.value(),
+            properties.s.value(),
+            properties.v.value(),
+            properties.a.value(),
+        )
+    }
+
+    ///The colour as `#rrggbb`, ignoring opacity
+    pub fn hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+
+    ///The colour as a css `rgba()` value, including opacity
+    pub fn css(&self) -> String {
+        format!("rgba({}, {}, {}, {})", self.r, self.g, self.b, self.a)
+    }
+}
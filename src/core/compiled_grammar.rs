@@ -0,0 +1,692 @@
+use std::{collections::BTreeMap, ops::Range};
+
+use crate::core::prelude::*;
+use itertools::Itertools;
+use rand::{prelude::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+///Index of an invocation in a compiled grammar. The root is always at index 0.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Serialize, Deserialize)]
+pub struct InvocationId(pub usize);
+
+impl InvocationId {
+    pub const ROOT: InvocationId = InvocationId(0);
+}
+
+///Index of a rule in a compiled grammar
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Serialize, Deserialize)]
+pub struct RuleId(pub usize);
+
+///Index of a variable in a compiled grammar
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Serialize, Deserialize)]
+pub struct VariableId(pub usize);
+
+///A single stack machine instruction
+#[derive(PartialEq, PartialOrd, Clone, Copy, Serialize, Deserialize)]
+pub enum Op {
+    Number(f32),
+    Variable(VariableId),
+    Property(PropertyKey),
+    Unary(UnaryOperator),
+    Binary(BinaryOperator),
+    ///Pop the end and the start and push a range between them
+    Range { is_random: bool },
+    ///Pop some ranges and push their union
+    Union { count: usize, is_random: bool },
+}
+
+///An expression flattened into stack bytecode
+#[derive(PartialEq, PartialOrd, Clone, Serialize, Deserialize)]
+pub struct CompiledExpression {
+    pub ops: Vec<Op>,
+}
+
+impl CompiledExpression {
+    fn compile_expression(
+        expression: &Expression,
+        variables: &BTreeMap<String, VariableId>,
+        ops: &mut Vec<Op>,
+    ) -> Result<(), String> {
+        match expression {
+            Expression::Number { val } => ops.push(Op::Number(*val)),
+            Expression::Variable { name } => {
+                let id = variables
+                    .get(&name.to_ascii_lowercase())
+                    .ok_or(format!("Variable '{}' not defined", name))?;
+                ops.push(Op::Variable(*id));
+            }
+            Expression::PropertyAccess { property } => ops.push(Op::Property(*property)),
+            Expression::Unary { operator, operand } => {
+                Self::compile_expression(operand, variables, ops)?;
+                ops.push(Op::Unary(*operator));
+            }
+            Expression::Binary {
+                left,
+                operator,
+                right,
+            } => {
+                Self::compile_expression(left, variables, ops)?;
+                Self::compile_expression(right, variables, ops)?;
+                ops.push(Op::Binary(*operator));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn compile(
+        expression: &Expression,
+        variables: &BTreeMap<String, VariableId>,
+    ) -> Result<Self, String> {
+        let mut ops = Vec::new();
+        Self::compile_expression(expression, variables, &mut ops)?;
+        Ok(Self { ops })
+    }
+
+    pub fn compile_range(
+        expression: &ExpressionOrRange,
+        variables: &BTreeMap<String, VariableId>,
+    ) -> Result<Self, String> {
+        match expression {
+            ExpressionOrRange::Range {
+                is_random,
+                first,
+                second,
+            } => {
+                let mut ops = Vec::new();
+                Self::compile_expression(first, variables, &mut ops)?;
+                Self::compile_expression(second, variables, &mut ops)?;
+                ops.push(Op::Range {
+                    is_random: *is_random,
+                });
+                Ok(Self { ops })
+            }
+            ExpressionOrRange::Compound { is_random, ranges } => {
+                let mut ops = Vec::new();
+                for (first, second) in ranges {
+                    Self::compile_expression(first, variables, &mut ops)?;
+                    Self::compile_expression(second, variables, &mut ops)?;
+                    ops.push(Op::Range { is_random: false });
+                }
+                ops.push(Op::Union {
+                    count: ranges.len(),
+                    is_random: *is_random,
+                });
+                Ok(Self { ops })
+            }
+            ExpressionOrRange::Exp(e) => Self::compile(e, variables),
+        }
+    }
+
+    ///The ids of the variables this expression reads
+    pub fn get_variables(&self) -> impl Iterator<Item = VariableId> + '_ {
+        self.ops.iter().filter_map(|op| match op {
+            Op::Variable(id) => Some(*id),
+            _ => None,
+        })
+    }
+
+    pub fn evaluate(
+        &self,
+        variables: &[f32],
+        context: &NodeProperties,
+        rng: &mut StdRng,
+    ) -> ValueOrRange {
+        self.evaluate_ops(variables, context, Some(rng))
+    }
+
+    ///Evaluate this expression without a node, leaving random ranges as ranges.
+    ///Returns `None` if the expression reads a property.
+    pub fn evaluate_static(&self, variables: &[f32]) -> Option<ValueOrRange> {
+        if self.ops.iter().any(|op| matches!(op, Op::Property(_))) {
+            return None;
+        }
+        Some(self.evaluate_ops(variables, &NodeProperties::default_additive(), None))
+    }
+
+    fn evaluate_ops(
+        &self,
+        variables: &[f32],
+        context: &NodeProperties,
+        mut rng: Option<&mut StdRng>,
+    ) -> ValueOrRange {
+        //Most expressions are a single value so skip the stack for them
+        if let [op] = self.ops.as_slice() {
+            match op {
+                Op::Number(val) => return ValueOrRange::Value(*val),
+                Op::Variable(id) => return ValueOrRange::Value(variables[id.0]),
+                Op::Property(key) => return key.get(context),
+                _ => {}
+            }
+        }
+
+        let mut stack = Vec::<ValueOrRange>::with_capacity(self.ops.len());
+
+        for op in self.ops.iter() {
+            let value = match op {
+                Op::Number(val) => ValueOrRange::Value(*val),
+                Op::Variable(id) => ValueOrRange::Value(variables[id.0]),
+                Op::Property(key) => key.get(context),
+                Op::Unary(operator) => operator.apply_range(stack.pop().unwrap()),
+                Op::Binary(operator) => {
+                    let right = stack.pop().unwrap();
+                    let left = stack.pop().unwrap();
+                    operator.apply_range(left, right)
+                }
+                Op::Range { is_random } => {
+                    let end = stack.pop().unwrap().max_value();
+                    let start = stack.pop().unwrap().min_value();
+
+                    match rng.as_mut() {
+                        Some(rng) if *is_random => {
+                            let v = if start <= end {
+                                rng.gen_range(start..=end)
+                            } else {
+                                rng.gen_range(end..=start)
+                            };
+                            ValueOrRange::Value(v)
+                        }
+                        _ => ValueOrRange::Range { start, end },
+                    }
+                }
+                Op::Union { count, is_random } => {
+                    let union = stack
+                        .drain(stack.len() - count..)
+                        .reduce(ValueOrRange::union)
+                        .unwrap_or_default();
+
+                    match rng.as_mut() {
+                        Some(rng) if *is_random => ValueOrRange::Value(union.random_value(rng)),
+                        _ => union,
+                    }
+                }
+            };
+            stack.push(value);
+        }
+
+        stack.pop().unwrap()
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Clone, Serialize, Deserialize)]
+pub struct CompiledProperty {
+    pub key: PropertyKey,
+    pub value: CompiledExpression,
+}
+
+#[derive(PartialEq, PartialOrd, Clone, Copy, Serialize, Deserialize)]
+pub enum CompiledMethod {
+    Root,
+    Primitive(Primitive),
+    Rule(RuleId),
+}
+
+#[derive(PartialEq, PartialOrd, Clone, Serialize, Deserialize)]
+pub struct CompiledInvocation {
+    pub method: CompiledMethod,
+    pub properties: Vec<CompiledProperty>,
+    pub symmetry: Option<Symmetry>,
+}
+
+#[derive(PartialEq, PartialOrd, Clone, Serialize, Deserialize)]
+pub struct CompiledCase {
+    pub probability: Option<CompiledExpression>,
+    pub invocations: Vec<InvocationId>,
+}
+
+#[derive(PartialEq, PartialOrd, Clone, Serialize, Deserialize)]
+pub struct CompiledRule {
+    pub name: String,
+    pub cases: Vec<CompiledCase>,
+}
+
+///A grammar with rules and variables interned and expressions flattened, ready for fast expansion
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
+pub struct CompiledGrammar {
+    pub variable_names: Vec<String>,
+    pub variables: Vec<f32>,
+    pub rules: Vec<CompiledRule>,
+    pub invocations: Vec<CompiledInvocation>,
+    pub top_level: Vec<InvocationId>,
+    ///The symmetry of the whole drawing, which is applied to every top level invocation
+    pub symmetry: Option<Symmetry>,
+    ///How far the subtree of each rule can reach, see `compute_rule_extents`
+    pub rule_extents: Vec<f32>,
+    ///The properties of the `canvas` directive, if there is one
+    pub canvas: Option<Vec<CompiledProperty>>,
+    ///Whether the canvas is fitted around the shapes drawn
+    pub fit_canvas: bool,
+    ///The properties of the `background` directive, if there is one
+    pub background: Option<Vec<CompiledProperty>>,
+    ///The frame declared by the directives, see `compute_frame`
+    pub frame: Frame,
+    ///Identifies the rules and invocations of this grammar, which setting variables does not change
+    pub structure: u64,
+}
+
+impl Grammar {
+    pub fn compile(&self) -> Result<CompiledGrammar, String> {
+        let variable_ids: BTreeMap<String, VariableId> = self
+            .defs
+            .keys()
+            .enumerate()
+            .map(|(i, name)| (name.clone(), VariableId(i)))
+            .collect();
+        let rule_ids: BTreeMap<&String, RuleId> = self
+            .rules
+            .keys()
+            .enumerate()
+            .map(|(i, name)| (name, RuleId(i)))
+            .collect();
+
+        let mut invocations = vec![CompiledInvocation {
+            method: CompiledMethod::Root,
+            properties: vec![],
+            symmetry: None,
+        }];
+
+        let mut compile_invocation = |invocation: &Invocation| -> Result<InvocationId, String> {
+            let method = match &invocation.method {
+                Method::Primitive(p) => CompiledMethod::Primitive(*p),
+                Method::Rule(name) => CompiledMethod::Rule(
+                    *rule_ids
+                        .get(name)
+                        .ok_or(format!("Rule '{}' does not exist", name))?,
+                ),
+            };
+            let properties = invocation
+                .properties
+                .iter()
+                .map(|p| {
+                    CompiledExpression::compile_range(&p.value, &variable_ids)
+                        .map(|value| CompiledProperty { key: p.key, value })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            invocations.push(CompiledInvocation {
+                method,
+                properties,
+                symmetry: invocation.symmetry,
+            });
+            Ok(InvocationId(invocations.len() - 1))
+        };
+
+        //Directives are evaluated once for the whole drawing, not for a node
+        let compile_directive = |properties: &Vec<TempProperty>| {
+            properties
+                .iter()
+                .map(|p| {
+                    let value = CompiledExpression::compile_range(&p.value, &variable_ids)?;
+                    if value.ops.iter().any(|op| matches!(op, Op::Property(_))) {
+                        return Err(format!("Directive property '{:?}' cannot read properties", p.key));
+                    }
+                    Ok(CompiledProperty { key: p.key, value })
+                })
+                .collect::<Result<Vec<_>, String>>()
+        };
+        let canvas = self
+            .canvas
+            .as_ref()
+            .map(|c| compile_directive(&c.properties))
+            .transpose()?;
+        let background = self.background.as_ref().map(compile_directive).transpose()?;
+
+        let top_level = self
+            .top_level
+            .iter()
+            .map(&mut compile_invocation)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut rules = Vec::with_capacity(self.rules.len());
+        for rule in self.rules.values() {
+            let mut cases = Vec::with_capacity(rule.cases.len());
+            for case in rule.cases.iter() {
+                let probability = match &case.probability {
+                    Some(p) => Some(CompiledExpression::compile(p, &variable_ids)?),
+                    None => None,
+                };
+                let case_invocations = case
+                    .invocations
+                    .iter()
+                    .map(&mut compile_invocation)
+                    .collect::<Result<Vec<_>, _>>()?;
+                cases.push(CompiledCase {
+                    probability,
+                    invocations: case_invocations,
+                });
+            }
+            rules.push(CompiledRule {
+                name: rule.name.clone(),
+                cases,
+            });
+        }
+
+        let mut grammar = CompiledGrammar {
+            variable_names: self.defs.keys().cloned().collect_vec(),
+            variables: self.defs.values().cloned().collect_vec(),
+            rules,
+            invocations,
+            top_level,
+            symmetry: self.symmetry,
+            rule_extents: vec![],
+            canvas,
+            fit_canvas: matches!(&self.canvas, Some(c) if c.fit),
+            background,
+            frame: Frame::default(),
+            structure: 0,
+        };
+        grammar.structure = grammar.compute_structure();
+        grammar.rule_extents = grammar.compute_rule_extents();
+        grammar.frame = grammar.compute_frame();
+        Ok(grammar)
+    }
+}
+
+///Where a variable is read
+#[derive(PartialEq, Clone, Default)]
+pub struct VariableDependencies {
+    ///The invocation properties which read the variable, by invocation and property index
+    pub properties: Vec<(InvocationId, usize)>,
+    ///The rule conditions which read the variable, by rule and case index
+    pub conditions: Vec<(RuleId, usize)>,
+}
+
+impl CompiledGrammar {
+    ///Find where each variable is read, indexed by variable id
+    pub fn get_dependencies(&self) -> Vec<VariableDependencies> {
+        let mut dependencies = vec![VariableDependencies::default(); self.variables.len()];
+
+        for (invocation, i) in self.invocations.iter().enumerate() {
+            for (property, p) in i.properties.iter().enumerate() {
+                for variable in p.value.get_variables() {
+                    dependencies[variable.0]
+                        .properties
+                        .push((InvocationId(invocation), property));
+                }
+            }
+        }
+
+        for (rule, r) in self.rules.iter().enumerate() {
+            for (case, c) in r.cases.iter().enumerate() {
+                for variable in c.probability.iter().flat_map(|p| p.get_variables()) {
+                    dependencies[variable.0].conditions.push((RuleId(rule), case));
+                }
+            }
+        }
+
+        dependencies
+    }
+
+    ///For each invocation, whether the children of a node with that invocation could be different if the given variables change.
+    ///A rule's children depend on its conditions and on the properties of every invocation in its cases.
+    pub fn get_affected_invocations(&self, changed: &[VariableId]) -> Vec<bool> {
+        let dependencies = self.get_dependencies();
+        let mut affected_rules = vec![false; self.rules.len()];
+
+        for d in changed.iter().map(|v| &dependencies[v.0]) {
+            for (rule, _) in d.conditions.iter() {
+                affected_rules[rule.0] = true;
+            }
+            for (invocation, _) in d.properties.iter() {
+                for (rule, r) in self.rules.iter().enumerate() {
+                    if r.cases.iter().any(|c| c.invocations.contains(invocation)) {
+                        affected_rules[rule] = true;
+                    }
+                }
+            }
+        }
+
+        self.invocations
+            .iter()
+            .map(|i| match i.method {
+                CompiledMethod::Rule(rule) => affected_rules[rule.0],
+                CompiledMethod::Root | CompiledMethod::Primitive(_) => false,
+            })
+            .collect_vec()
+    }
+
+    ///A hash of everything in this grammar except the values of its variables
+    fn compute_structure(&self) -> u64 {
+        use std::hash::{Hash, Hasher};
+
+        let structure = (
+            &self.variable_names,
+            &self.rules,
+            &self.invocations,
+            &self.top_level,
+            &self.symmetry,
+            &self.canvas,
+            self.fit_canvas,
+            &self.background,
+        );
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        //Every field is a plain value so this cannot fail
+        serde_json::to_string(&structure).unwrap().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    pub fn get_invocation(&self, id: InvocationId) -> &CompiledInvocation {
+        &self.invocations[id.0]
+    }
+
+    pub fn get_variable_id(&self, name: &str) -> Option<VariableId> {
+        let name = name.to_ascii_lowercase();
+        self.variable_names
+            .iter()
+            .position(|n| *n == name)
+            .map(VariableId)
+    }
+
+    ///Set the value of a variable. Returns false if there is no such variable.
+    pub fn set_variable(&mut self, name: &str, value: f32) -> bool {
+        match self.get_variable_id(name) {
+            Some(id) => {
+                self.variables[id.0] = value;
+                self.rule_extents = self.compute_rule_extents();
+                self.frame = self.compute_frame();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn override_variables(&mut self, new_defs: &BTreeMap<String, f32>) {
+        for (key, val) in new_defs {
+            if let Some(id) = self.get_variable_id(key) {
+                self.variables[id.0] = *val;
+            }
+        }
+        self.rule_extents = self.compute_rule_extents();
+        self.frame = self.compute_frame();
+    }
+
+    ///Evaluate the properties of an invocation relative to the context
+    pub fn relative_properties(
+        &self,
+        id: InvocationId,
+        context: &NodeProperties,
+        rng: &mut StdRng,
+    ) -> NodeProperties {
+        let mut properties = NodeProperties::default_additive();
+
+        for prop in self.get_invocation(id).properties.iter() {
+            let value = prop.value.evaluate(&self.variables, context, rng);
+            prop.key.set(&mut properties, value);
+        }
+
+        properties
+    }
+
+    fn should_enter(&self, case: &CompiledCase, context: &NodeProperties, rng: &mut StdRng) -> bool {
+        if let Some(value) = &case.probability {
+            let prob = value.evaluate(&self.variables, context, rng);
+            if prob.min_value() >= 1.0 {
+                true
+            } else if prob.max_value() <= 0.0 {
+                false
+            } else {
+                //The chance of entering is the largest value the probability could take
+                rng.gen_bool(prob.max_value().clamp(0.0, 1.0).into())
+            }
+        } else {
+            true
+        }
+    }
+
+    pub fn to_node(
+        &self,
+        id: InvocationId,
+        parent_properties: &NodeProperties,
+        seed: u64,
+        rng: &mut StdRng,
+    ) -> Node {
+        //Ranges are resolved here so that drawing the node needs no randomness
+        let relative_properties = self.relative_properties(id, parent_properties, rng).sample(rng);
+        self.place_node(id, parent_properties, &relative_properties, seed)
+    }
+
+    ///Create a node placed by relative properties whose ranges are resolved
+    pub(crate) fn place_node(
+        &self,
+        id: InvocationId,
+        parent_properties: &NodeProperties,
+        relative_properties: &NodeProperties,
+        seed: u64,
+    ) -> Node {
+        let absolute_properties = parent_properties.make_absolute(relative_properties);
+        let mut transform = RelativeTransform::from_properties(relative_properties);
+        //Groups are never reflected, so the transform of a child of a mirrored node is reflected instead
+        if parent_properties.mirrored {
+            transform.x = -transform.x;
+            transform.r = -transform.r;
+        }
+        Node {
+            invocation: id,
+            transform,
+            absolute_properties,
+            seed,
+            children: None,
+        }
+    }
+
+    ///Create the children of a node.
+    ///The seed is the node's own, so the children do not depend on the order in which nodes are expanded
+    pub fn get_children(
+        &self,
+        id: InvocationId,
+        absolute_properties: &NodeProperties,
+        seed: u64,
+    ) -> Vec<Node> {
+        self.choose_children(id, absolute_properties, seed).1
+    }
+
+    ///Create the children of a node, also returning the index of the case they were made from
+    pub fn choose_children(
+        &self,
+        id: InvocationId,
+        absolute_properties: &NodeProperties,
+        seed: u64,
+    ) -> (Option<usize>, Vec<Node>) {
+        match self.get_invocation(id).method {
+            CompiledMethod::Root | CompiledMethod::Primitive(_) => Default::default(),
+            CompiledMethod::Rule(rule) => {
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut rng1 = StdRng::from_seed(rng.gen());
+                let mut rng2 = StdRng::from_seed(rng.gen());
+
+                let case = self.rules[rule.0]
+                    .cases
+                    .iter()
+                    .position(|c| self.should_enter(c, absolute_properties, &mut rng1)); //only take the first condition which matches
+
+                let children = case
+                    .map(|case| {
+                        self.rules[rule.0].cases[case]
+                            .invocations
+                            .iter()
+                            .enumerate()
+                            .flat_map(|(index, i)| {
+                                let child_seed = Node::child_seed(seed, index);
+                                self.to_nodes(*i, absolute_properties, None, child_seed, &mut rng2)
+                            })
+                            .collect_vec()
+                    })
+                    .unwrap_or_default();
+                (case, children)
+            }
+        }
+    }
+
+    ///Create the root node and the nodes for the top level invocations
+    pub fn make_root(&self, rng: &mut StdRng) -> (Node, Vec<Node>) {
+        let root = Node {
+            invocation: InvocationId::ROOT,
+            transform: RelativeTransform::IDENTITY,
+            absolute_properties: NodeProperties::default_initial(),
+            seed: rng.gen(),
+            children: None,
+        };
+
+        let mut root_rng = StdRng::seed_from_u64(root.seed);
+        let top_level = self
+            .top_level
+            .iter()
+            .enumerate()
+            .flat_map(|(index, i)| {
+                let seed = Node::child_seed(root.seed, index);
+                self.to_nodes(
+                    *i,
+                    &root.absolute_properties,
+                    self.symmetry.as_ref(),
+                    seed,
+                    &mut root_rng,
+                )
+            })
+            .collect_vec();
+
+        (root, top_level)
+    }
+
+    pub fn expand(&self, settings: &ExpandSettings, rng: &mut StdRng) -> NodeTree {
+        self.expand_with(settings, rng, |tree, frontier, budget| {
+            tree.expand_frontier(frontier, budget, |node| {
+                let mut stats = ExpandStatistics::default();
+                (node.get_children(settings, self, &mut stats), stats)
+            })
+        })
+    }
+
+    ///Expand pass by pass, using `expand_frontier` to expand the nodes made in the previous pass.
+    ///It returns the statistics for each node it expanded, in order.
+    pub(crate) fn expand_with<F>(
+        &self,
+        settings: &ExpandSettings,
+        rng: &mut StdRng,
+        mut expand_frontier: F,
+    ) -> NodeTree
+    where
+        F: FnMut(&mut NodeTree, Range<usize>, &mut ExpandBudget) -> Vec<ExpandStatistics>,
+    {
+        let mut current = ExpandStatistics::default();
+        let mut budget = ExpandBudget::new(settings);
+        let (root, top_level) = self.make_root(rng);
+
+        let mut tree = NodeTree::new(root, top_level);
+        tree.profile = ExpansionProfile::new(self);
+        let mut frontier = 1..tree.nodes.len();
+        while !frontier.is_empty() && budget.truncated.is_none() {
+            let node_stats = expand_frontier(&mut tree, frontier.clone(), &mut budget);
+            for (index, stats) in frontier.clone().zip(node_stats.iter()) {
+                tree.profile.record(self, &tree.nodes[index], stats);
+                current = current + stats;
+            }
+            frontier = frontier.end..tree.nodes.len();
+        }
+
+        current.truncated = budget.truncated;
+        tree.statistics = current;
+        if settings.occlusion_culling {
+            tree.cull_occluded(self);
+        }
+        tree
+    }
+}
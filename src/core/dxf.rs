@@ -0,0 +1,315 @@
+use std::fmt::Write;
+
+use crate::core::prelude::*;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+///How shapes are put on layers
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum DxfLayers {
+    ///Every shape is on layer `0`
+    Single,
+    ///Shapes are on the layer of the rule which drew them
+    RuleName,
+    ///Shapes are on a layer for their value of a property, rounded down to a multiple of the step
+    Property { key: PropertyKey, step: f32 },
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct DxfSettings {
+    ///The width of the canvas in millimetres
+    pub size: f32,
+    ///The largest distance, in millimetres, between an ellipse and the lines drawn in its place
+    pub tolerance: f32,
+    pub layers: DxfLayers,
+}
+
+impl Default for DxfSettings {
+    fn default() -> Self {
+        Self {
+            size: 200.0,
+            tolerance: 0.05,
+            layers: DxfLayers::RuleName,
+        }
+    }
+}
+
+///A shape in absolute coordinates, in millimetres from the bottom left of the canvas
+enum DxfEntity {
+    Circle {
+        centre: (f32, f32),
+        radius: f32,
+    },
+    ///Each vertex has the bulge of the arc from it to the next vertex, which is zero for a straight line
+    ClosedPolyline(Vec<((f32, f32), f32)>),
+}
+
+///A layer name containing only characters allowed in every version of DXF
+fn layer_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '$' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect()
+}
+
+///The closest colour in the standard AutoCAD palette
+fn color_index(color: &Rgba) -> u8 {
+    let (r, g, b) = (color.r as f32, color.g as f32, color.b as f32);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    if max - min < 32.0 {
+        //White, which is shown as black on a light background
+        return 7;
+    }
+    let hue = if max == r {
+        (g - b) / (max - min) * 60.0
+    } else if max == g {
+        (b - r) / (max - min) * 60.0 + 120.0
+    } else {
+        (r - g) / (max - min) * 60.0 + 240.0
+    };
+    //Colours 10 to 240 go around the colour wheel in steps of 15 degrees
+    10 + ((hue.rem_euclid(360.0) / 15.0).round() as u8 % 24) * 10
+}
+
+impl Primitive {
+    fn dxf_entity(
+        &self,
+        properties: &NodeProperties,
+        to_millimetres: impl Fn((f32, f32)) -> (f32, f32),
+        tolerance: f32,
+    ) -> DxfEntity {
+        let p = properties.p.value();
+        let half_width = (p * properties.w.value()).abs();
+        let half_height = (p * properties.l.value()).abs();
+        let corner = p * properties.c.value();
+        let (corner_x, corner_y) = (corner.min(half_width), corner.min(half_height));
+        let (sin, cos) = properties.r.value().to_radians().sin_cos();
+        let (centre_x, centre_y) = (properties.x.value(), properties.y.value());
+        //Shapes rotate about their own centre
+        let place = |(x, y): (f32, f32)| {
+            to_millimetres((centre_x + x * cos - y * sin, centre_y + x * sin + y * cos))
+        };
+
+        match self {
+            Primitive::Circle if half_width == half_height => DxfEntity::Circle {
+                centre: to_millimetres((centre_x, centre_y)),
+                radius: (to_millimetres((half_width, 0.0)).0 - to_millimetres((0.0, 0.0)).0).abs(),
+            },
+            //Bulges can only draw circular arcs, so elliptical corners use the outline
+            Primitive::Square if corner > 0.0 && corner_x == corner_y => {
+                let (w, h, c) = (half_width, half_height, corner_x);
+                //Each corner is an arc from one side to the next
+                let vertices = [
+                    ((w, h - c), true),
+                    ((w - c, h), false),
+                    ((c - w, h), true),
+                    ((-w, h - c), false),
+                    ((-w, c - h), true),
+                    ((c - w, -h), false),
+                    ((w - c, -h), true),
+                    ((w, c - h), false),
+                ]
+                .map(|(point, is_arc)| (place(point), is_arc));
+
+                //A quarter circle has a bulge of tan(90° / 4), which is negative if it goes clockwise
+                let anticlockwise = signed_area(&vertices.iter().map(|v| v.0).collect_vec()) > 0.0;
+                let bulge =
+                    std::f32::consts::FRAC_PI_8.tan() * if anticlockwise { 1.0 } else { -1.0 };
+                DxfEntity::ClosedPolyline(
+                    vertices
+                        .into_iter()
+                        .map(|(point, is_arc)| (point, if is_arc { bulge } else { 0.0 }))
+                        .collect_vec(),
+                )
+            }
+            _ => {
+                let scale = (to_millimetres((1.0, 0.0)).0 - to_millimetres((0.0, 0.0)).0).abs();
+                DxfEntity::ClosedPolyline(
+                    self.outline(properties, tolerance / scale)
+                        .into_iter()
+                        .map(|point| (to_millimetres(point), 0.0))
+                        .collect_vec(),
+                )
+            }
+        }
+    }
+}
+
+fn signed_area(points: &[(f32, f32)]) -> f32 {
+    points
+        .iter()
+        .zip(points.iter().cycle().skip(1))
+        .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+        .sum::<f32>()
+        / 2.0
+}
+
+///Draws the outline of every shape as a DXF R12 drawing, in millimetres
+struct DxfRenderer {
+    settings: DxfSettings,
+    canvas: Bounds,
+    ///How many millimetres there are to one unit of the canvas
+    scale: f32,
+    ///The name and colour of each layer, in the order they are first used
+    layers: Vec<(String, u8)>,
+    entities: String,
+}
+
+impl DxfRenderer {
+    fn new(settings: &DxfSettings) -> Self {
+        Self {
+            settings: *settings,
+            canvas: Bounds::CANVAS,
+            scale: 1.0,
+            layers: vec![],
+            entities: String::new(),
+        }
+    }
+
+    fn to_millimetres(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        //DXF coordinates go up the page
+        (
+            (x - self.canvas.min_x) * self.scale,
+            (self.canvas.max_y - y) * self.scale,
+        )
+    }
+
+    ///The name of the layer a shape is on
+    fn layer(&self, shape: &RenderShape) -> String {
+        match &self.settings.layers {
+            DxfLayers::Single => "0".to_string(),
+            DxfLayers::RuleName => match shape.rule_path.last() {
+                Some(rule) => layer_name(rule),
+                None => "0".to_string(),
+            },
+            DxfLayers::Property { key, step } => {
+                let value = key.get(&shape.node.absolute_properties).value();
+                let value = if *step > 0.0 {
+                    (value / step).floor() * step
+                } else {
+                    value
+                };
+                layer_name(&format!("{key:?}_{}", SvgNumber(value, Some(3))))
+            }
+        }
+    }
+
+    fn write_shape(&mut self, shape: &RenderShape) -> std::fmt::Result {
+        let layer = self.layer(shape);
+        if !self.layers.iter().any(|(name, _)| *name == layer) {
+            self.layers.push((layer.clone(), color_index(&shape.color)));
+        }
+
+        let entity = shape.primitive.dxf_entity(
+            &shape.node.absolute_properties,
+            |p| self.to_millimetres(p),
+            self.settings.tolerance,
+        );
+        let entities = &mut self.entities;
+        match entity {
+            DxfEntity::Circle { centre, radius } => {
+                write_group(entities, 0, "CIRCLE")?;
+                write_group(entities, 8, &layer)?;
+                write_point(entities, centre)?;
+                write_group(entities, 40, radius)?;
+            }
+            DxfEntity::ClosedPolyline(vertices) => {
+                write_group(entities, 0, "POLYLINE")?;
+                write_group(entities, 8, &layer)?;
+                write_group(entities, 66, 1)?;
+                write_point(entities, (0.0, 0.0))?;
+                write_group(entities, 70, 1)?;
+                for (point, bulge) in vertices {
+                    write_group(entities, 0, "VERTEX")?;
+                    write_group(entities, 8, &layer)?;
+                    write_point(entities, point)?;
+                    if bulge != 0.0 {
+                        write_group(entities, 42, bulge)?;
+                    }
+                }
+                write_group(entities, 0, "SEQEND")?;
+                write_group(entities, 8, &layer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Renderer for DxfRenderer {
+    type Output = String;
+
+    fn begin(&mut self, frame: &Frame) {
+        self.canvas = frame.bounds;
+        self.scale = self.settings.size / frame.bounds.width();
+    }
+
+    fn draw_primitive(&mut self, shape: &RenderShape) {
+        //Writing to a string cannot fail
+        let _ = self.write_shape(shape);
+    }
+
+    fn finish(self) -> Self::Output {
+        let mut dxf = String::new();
+        let _ = (|| -> std::fmt::Result {
+            write_group(&mut dxf, 0, "SECTION")?;
+            write_group(&mut dxf, 2, "HEADER")?;
+            write_group(&mut dxf, 9, "$ACADVER")?;
+            write_group(&mut dxf, 1, "AC1009")?;
+            //R12 has no header variable for units, so the unit of millimetres is only documented on `to_dxf`
+            write_group(&mut dxf, 0, "ENDSEC")?;
+
+            write_group(&mut dxf, 0, "SECTION")?;
+            write_group(&mut dxf, 2, "TABLES")?;
+            write_group(&mut dxf, 0, "TABLE")?;
+            write_group(&mut dxf, 2, "LTYPE")?;
+            write_group(&mut dxf, 70, 1)?;
+            write_group(&mut dxf, 0, "LTYPE")?;
+            write_group(&mut dxf, 2, "CONTINUOUS")?;
+            write_group(&mut dxf, 70, 0)?;
+            write_group(&mut dxf, 3, "Solid line")?;
+            write_group(&mut dxf, 72, 65)?;
+            write_group(&mut dxf, 73, 0)?;
+            write_group(&mut dxf, 40, 0.0)?;
+            write_group(&mut dxf, 0, "ENDTAB")?;
+            write_group(&mut dxf, 0, "TABLE")?;
+            write_group(&mut dxf, 2, "LAYER")?;
+            write_group(&mut dxf, 70, self.layers.len())?;
+            for (name, color) in self.layers.iter() {
+                write_group(&mut dxf, 0, "LAYER")?;
+                write_group(&mut dxf, 2, name)?;
+                write_group(&mut dxf, 70, 0)?;
+                write_group(&mut dxf, 62, color)?;
+                write_group(&mut dxf, 6, "CONTINUOUS")?;
+            }
+            write_group(&mut dxf, 0, "ENDTAB")?;
+            write_group(&mut dxf, 0, "ENDSEC")?;
+
+            write_group(&mut dxf, 0, "SECTION")?;
+            write_group(&mut dxf, 2, "ENTITIES")?;
+            dxf.push_str(&self.entities);
+            write_group(&mut dxf, 0, "ENDSEC")?;
+            write_group(&mut dxf, 0, "EOF")
+        })();
+        dxf
+    }
+}
+
+impl NodeTree {
+    ///Draw the outline of every shape as a DXF R12 drawing, in millimetres
+    pub fn to_dxf(&self, grammar: &CompiledGrammar, settings: &DxfSettings) -> String {
+        self.render(grammar, DxfRenderer::new(settings))
+    }
+}
+
+fn write_group(dxf: &mut String, code: u16, value: impl std::fmt::Display) -> std::fmt::Result {
+    writeln!(dxf, "{code:>3}\n{value}")
+}
+
+fn write_point(dxf: &mut String, (x, y): (f32, f32)) -> std::fmt::Result {
+    write_group(dxf, 10, x)?;
+    write_group(dxf, 20, y)?;
+    write_group(dxf, 30, 0.0)
+}
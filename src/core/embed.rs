@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+
+use crate::core::prelude::*;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+///The svg metadata id and png text keyword under which the source of a picture is stored
+pub const EMBED_KEYWORD: &str = "convext";
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+///Everything needed to draw a picture again, which is stored in exported files
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmbeddedSource {
+    ///The convext program
+    pub source: String,
+    pub seed: u64,
+    pub settings: ExpandSettings,
+    ///The values of variables which were changed from those in the source
+    #[serde(default)]
+    pub overrides: BTreeMap<String, f32>,
+}
+
+impl EmbeddedSource {
+    pub fn to_json(&self) -> String {
+        //Every field is a plain value so this cannot fail
+        serde_json::to_string(self).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+
+    ///Compile the source and expand it as it was when the picture was made
+    pub fn draw(&self) -> Result<(CompiledGrammar, NodeTree), String> {
+        let mut grammar = parse(&self.source)?.compile()?;
+        grammar.override_variables(&self.overrides);
+        let mut rng = SeedableRng::seed_from_u64(self.seed);
+        let tree = grammar.expand(&self.settings, &mut rng);
+        Ok((grammar, tree))
+    }
+
+    ///Put this in the `<metadata>` of an svg, as the first child of the root element
+    pub fn embed_in_svg(&self, svg: &str) -> String {
+        let mut metadata = SvgWriter::new(String::new());
+        //Writing to a string cannot fail
+        let _ = metadata.text_element("metadata", &[("id", &EMBED_KEYWORD)], &self.to_json());
+        let metadata = metadata.into_inner();
+
+        let root_end = svg
+            .find("<svg")
+            .and_then(|start| svg[start..].find('>').map(|end| start + end + 1));
+        match root_end {
+            Some(index) => {
+                let (root, rest) = svg.split_at(index);
+                format!("{root}\n{metadata}{}", rest.trim_start_matches('\n'))
+            }
+            None => svg.to_string(),
+        }
+    }
+
+    ///Encode an image as a png with this in a text chunk
+    pub fn embed_in_png(&self, image: &RgbaImage) -> Result<Vec<u8>, String> {
+        let mut png = vec![];
+        image.write_png_with_text(&mut png, &[(EMBED_KEYWORD, &self.to_json())])?;
+        Ok(png)
+    }
+
+    ///Read the source embedded in an svg by `embed_in_svg`
+    pub fn from_svg(svg: &str) -> Result<Self, String> {
+        let start_tag = format!("<metadata id=\"{EMBED_KEYWORD}\">");
+        let start = svg
+            .find(&start_tag)
+            .ok_or("The svg has no convext metadata")?
+            + start_tag.len();
+        let end = svg[start..]
+            .find("</metadata>")
+            .ok_or("The convext metadata is not closed")?
+            + start;
+
+        //Ampersands are unescaped last so that escaped entities are not unescaped twice
+        let json = svg[start..end]
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&");
+        Self::from_json(&json)
+    }
+
+    ///Read the source embedded in a png by `embed_in_png`
+    pub fn from_png(png: &[u8]) -> Result<Self, String> {
+        let reader = png::Decoder::new(png)
+            .read_info()
+            .map_err(|e| e.to_string())?;
+        let chunk = reader
+            .info()
+            .utf8_text
+            .iter()
+            .find(|chunk| chunk.keyword == EMBED_KEYWORD)
+            .ok_or("The png has no convext text")?;
+        Self::from_json(&chunk.get_text().map_err(|e| e.to_string())?)
+    }
+
+    ///Read the source embedded in an exported svg or png file
+    pub fn import(file: &[u8]) -> Result<Self, String> {
+        if file.starts_with(PNG_SIGNATURE) {
+            Self::from_png(file)
+        } else {
+            let svg =
+                std::str::from_utf8(file).map_err(|_| "The file is neither a png nor an svg")?;
+            Self::from_svg(svg)
+        }
+    }
+}
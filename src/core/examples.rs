@@ -76,8 +76,8 @@ let angleRight 15
 let angleLeft 330
 
 rul grow ?probBranch
-grow  r ?angl
-grow  r ?angr
+grow  r ?angleLeft
+grow  r ?angleRight
 
 rul grow
 square l 4 w0.5 ysub5
@@ -15,20 +15,121 @@ pub struct ExpandSettings {
     pub max_depth: usize,
     pub min_a: f32,
     pub min_p: f32,
+    ///The maximum number of nodes to expand
+    #[serde(default)]
+    pub max_steps: Option<usize>,
+    ///The maximum time to spend expanding
+    #[serde(default)]
+    pub max_milliseconds: Option<u32>,
+    ///Nodes which cannot draw anything inside the viewport, grown by the margin, are culled.
+    ///A grammar which declares a canvas uses that instead.
+    #[serde(default = "default_viewport")]
+    pub viewport: Bounds,
+    #[serde(default = "default_cull_margin")]
+    pub cull_margin: f32,
+    ///Remove shapes which are hidden by later opaque shapes after expanding
+    #[serde(default)]
+    pub occlusion_culling: bool,
+}
+
+fn default_viewport() -> Bounds {
+    Bounds::CANVAS
+}
+
+pub fn default_cull_margin() -> f32 {
+    0.5
 }
 
 impl ExpandSettings {
-    ///Should this node be culled, according to the settings
-    pub fn should_cull(&self, node: &Node) -> bool {
-        if node.absolute_properties.a.max_value() < self.min_a
-            || node.absolute_properties.d > self.max_depth
-            || node.absolute_properties.p.max_value() * node.absolute_properties.w.max_value() < self.min_p
-            || node.absolute_properties.p.max_value() * node.absolute_properties.l.max_value() < self.min_p
-            || node.absolute_properties.x.max_abs() - node.absolute_properties.p.min_value() > 3.0
+    ///Why this node should be culled, according to the settings, or `None` if it should be kept
+    pub fn should_cull(&self, node: &Node, grammar: &CompiledGrammar) -> Option<CullReason> {
+        let properties = &node.absolute_properties;
+        if properties.a.max_value() < self.min_a {
+            Some(CullReason::Transparent)
+        } else if properties.d > self.max_depth {
+            Some(CullReason::TooDeep)
+        } else if properties.p.max_value() * properties.w.max_value() < self.min_p
+            || properties.p.max_value() * properties.l.max_value() < self.min_p
         {
-            true
+            Some(CullReason::TooSmall)
+        } else if !grammar
+            .get_bounds(node)
+            .intersects(&grammar.viewport(self).grow(self.cull_margin))
+        {
+            Some(CullReason::OutOfBounds)
         } else {
-            node.absolute_properties.y.max_abs() - node.absolute_properties.p.min_value() > 3.0
+            None
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now_milliseconds() -> f64 {
+    js_sys::Date::now()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn now_milliseconds() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs_f64() * 1000.0)
+        .unwrap_or_default()
+}
+
+///Tracks how much of the node, step and time budgets remain during an expansion
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub struct ExpandBudget {
+    pub remaining_nodes: usize,
+    pub remaining_steps: Option<usize>,
+    pub deadline: Option<f64>,
+    pub truncated: Option<TruncationReason>,
+}
+
+impl ExpandBudget {
+    pub fn new(settings: &ExpandSettings) -> Self {
+        Self {
+            remaining_nodes: settings.max_nodes,
+            remaining_steps: settings.max_steps,
+            deadline: settings
+                .max_milliseconds
+                .map(|ms| now_milliseconds() + ms as f64),
+            truncated: None,
+        }
+    }
+
+    ///Spend the budget on the children made by expanding a node.
+    ///Returns `None` if the budget has run out and the node should be left unexpanded.
+    ///Children beyond the node budget are dropped so the budget is never exceeded.
+    pub fn take(
+        &mut self,
+        mut children: Vec<Node>,
+        mut stats: ExpandStatistics,
+    ) -> Option<(Vec<Node>, ExpandStatistics)> {
+        if self.truncated.is_some() {
+            return None;
+        }
+        if self.remaining_steps == Some(0) {
+            self.truncated = Some(TruncationReason::MaxSteps);
+            return None;
+        }
+        if self.deadline.is_some_and(|d| now_milliseconds() > d) {
+            self.truncated = Some(TruncationReason::MaxTime);
+            return None;
+        }
+        if !children.is_empty() && self.remaining_nodes == 0 {
+            self.truncated = Some(TruncationReason::MaxNodes);
+            return None;
+        }
+
+        if children.len() > self.remaining_nodes {
+            children.truncate(self.remaining_nodes);
+            stats.new_nodes = self.remaining_nodes;
+            stats.truncated = Some(TruncationReason::MaxNodes);
+            self.truncated = Some(TruncationReason::MaxNodes);
+        }
+        self.remaining_nodes -= children.len();
+        self.remaining_steps = self.remaining_steps.map(|s| s - 1);
+
+        Some((children, stats))
+    }
+}
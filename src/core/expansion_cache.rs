@@ -0,0 +1,176 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::core::prelude::*;
+use itertools::Itertools;
+use rand::prelude::StdRng;
+
+#[derive(PartialEq, Clone, Copy)]
+struct CacheEntry {
+    ///The index of the node in the cached tree, whose children are reused
+    index: usize,
+    stats: ExpandStatistics,
+}
+
+///What the cached tree was expanded with
+#[derive(PartialEq, Clone)]
+struct CacheKey {
+    structure: u64,
+    variables: Vec<f32>,
+    rule_extents: Vec<f32>,
+    viewport: Bounds,
+    settings: ExpandSettings,
+}
+
+///Remembers how each node was expanded, so that expanding again after a variable changes
+///can reuse the subtrees which do not depend on that variable
+#[derive(PartialEq, Clone, Default)]
+pub struct ExpansionCache {
+    key: Option<CacheKey>,
+    ///The tree made by the last expansion, before any occlusion culling
+    tree: Option<NodeTree>,
+    ///The tree made by the last expansion with occluded nodes removed, if occlusion culling is on
+    culled: Option<NodeTree>,
+    entries: HashMap<(u64, InvocationId), CacheEntry>,
+    ///The number of nodes whose children were reused in the last expansion,
+    ///which may include a few whose children were then cut off by the budget
+    pub reused: usize,
+}
+
+impl ExpansionCache {
+    ///The tree made by the last expansion
+    pub fn tree(&self) -> Option<&NodeTree> {
+        self.culled.as_ref().or(self.tree.as_ref())
+    }
+
+    ///For each invocation, whether nodes with that invocation must be expanded again, or `None` if the cache cannot be used at all
+    fn affected_invocations(
+        &self,
+        grammar: &CompiledGrammar,
+        settings: &ExpandSettings,
+    ) -> Option<Vec<bool>> {
+        let previous = self.key.as_ref()?;
+        if previous.settings != *settings
+            || previous.structure != grammar.structure
+            || previous.viewport != grammar.viewport(settings)
+        {
+            return None;
+        }
+
+        let changed = previous
+            .variables
+            .iter()
+            .zip(grammar.variables.iter())
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(i, _)| VariableId(i))
+            .collect_vec();
+        let mut affected = grammar.get_affected_invocations(&changed);
+
+        //Children are culled by their bounds, which depend on the extents of their rules
+        for (rule, r) in grammar.rules.iter().enumerate() {
+            let makes_changed_extent = r.cases.iter().flat_map(|c| c.invocations.iter()).any(|i| {
+                match grammar.get_invocation(*i).method {
+                    CompiledMethod::Rule(child) => {
+                        previous.rule_extents[child.0] != grammar.rule_extents[child.0]
+                    }
+                    CompiledMethod::Root | CompiledMethod::Primitive(_) => false,
+                }
+            });
+            if makes_changed_extent {
+                for (invocation, i) in grammar.invocations.iter().enumerate() {
+                    if i.method == CompiledMethod::Rule(RuleId(rule)) {
+                        affected[invocation] = true;
+                    }
+                }
+            }
+        }
+        Some(affected)
+    }
+}
+
+impl CompiledGrammar {
+    ///Expand this grammar, reusing the children of nodes from the previous expansion with this cache
+    ///when they were made from the same seed and properties and do not read any changed variable.
+    ///The result, which is kept in the cache, is identical to `expand`.
+    pub fn expand_cached<'a>(
+        &self,
+        settings: &ExpandSettings,
+        rng: &mut StdRng,
+        cache: &'a mut ExpansionCache,
+    ) -> &'a NodeTree {
+        let affected = cache
+            .affected_invocations(self, settings)
+            .unwrap_or_else(|| vec![true; self.invocations.len()]);
+        let previous_entries = std::mem::take(&mut cache.entries);
+        let previous_tree = cache.tree.take();
+        //The children of a node in the previous tree, if they can be reused for this node
+        let reusable = |node: &Node| -> Option<(&[Node], ExpandStatistics)> {
+            if affected[node.invocation.0] {
+                return None;
+            }
+            let tree = previous_tree.as_ref()?;
+            let entry = previous_entries.get(&(node.seed, node.invocation))?;
+            let previous = &tree.nodes[entry.index];
+            if previous.absolute_properties != node.absolute_properties {
+                return None;
+            }
+            Some((tree.children(previous), entry.stats))
+        };
+        let mut entries = HashMap::new();
+        //Nodes may be expanded in parallel, so they are counted atomically
+        let reused = AtomicUsize::new(0);
+
+        //Occlusion culling moves nodes, so it is done on a copy to keep the indices of the cached nodes
+        let expand_settings = ExpandSettings {
+            occlusion_culling: false,
+            ..*settings
+        };
+        let tree = self.expand_with(&expand_settings, rng, |tree, frontier, budget| {
+            let node_stats = tree.expand_frontier(frontier.clone(), budget, |node| match reusable(node) {
+                Some((children, stats)) => {
+                    reused.fetch_add(1, Ordering::Relaxed);
+                    let children = children
+                        .iter()
+                        .map(|c| Node {
+                            children: None,
+                            ..c.clone()
+                        })
+                        .collect_vec();
+                    (children, stats)
+                }
+                None => {
+                    let mut stats = ExpandStatistics::default();
+                    (node.get_children(settings, self, &mut stats), stats)
+                }
+            });
+
+            for (index, stats) in frontier.zip(node_stats.iter().copied()) {
+                let node = &tree.nodes[index];
+                if stats.truncated.is_some() {
+                    continue; //Only some of this node's children were kept
+                }
+                entries.insert((node.seed, node.invocation), CacheEntry { index, stats });
+            }
+            node_stats
+        });
+
+        cache.entries = entries;
+        cache.reused = reused.into_inner();
+        cache.key = Some(CacheKey {
+            structure: self.structure,
+            variables: self.variables.clone(),
+            rule_extents: self.rule_extents.clone(),
+            viewport: self.viewport(settings),
+            settings: *settings,
+        });
+        cache.culled = settings.occlusion_culling.then(|| {
+            let mut culled = tree.clone();
+            culled.cull_occluded(self);
+            culled
+        });
+        cache.tree = Some(tree);
+        //The tree was just stored
+        cache.tree().unwrap()
+    }
+}
@@ -18,6 +18,11 @@ pub enum ExpressionOrRange {
         first: Expression,
         second: Expression,
     },
+    ///A union of ranges, such as `0..1, 2..3`
+    Compound {
+        is_random: bool,
+        ranges: Vec<(Expression, Expression)>,
+    },
     Exp(Expression),
 }
 
@@ -29,6 +34,13 @@ impl ExpressionOrRange {
                 first,
                 second,
             } => Box::new(first.get_variables().chain(second.get_variables())),
+            ExpressionOrRange::Compound { is_random, ranges } => Box::new(
+                ranges
+                    .iter()
+                    .flat_map(|(first, second)| first.get_variables().chain(second.get_variables()))
+                    .collect_vec()
+                    .into_iter(),
+            ),
             ExpressionOrRange::Exp(e) => e.get_variables(),
         }
     }
@@ -58,10 +70,47 @@ impl ExpressionOrRange {
                     Ok(ValueOrRange::Range { start, end }) 
                 }
             },
+            ExpressionOrRange::Compound { is_random, ranges } => {
+                let mut union = None;
+                for (first, second) in ranges {
+                    let start = first.try_get_value(grammar, context, rng)?.min_value();
+                    let end = second.try_get_value(grammar, context, rng)?.max_value();
+                    let range = ValueOrRange::Range { start, end };
+                    union = Some(union.map_or(range, |u: ValueOrRange| u.union(range)));
+                }
+                let union = union.unwrap_or_default();
+
+                if *is_random {
+                    Ok(ValueOrRange::Value(union.random_value(rng)))
+                } else {
+                    Ok(union)
+                }
+            }
             ExpressionOrRange::Exp(e) => e.try_get_value(grammar, context, rng),
         }
     }
 
+    fn parse_range(range: Pair<Rule>, is_random: bool) -> Result<Self, String> {
+        let mut ranges = range
+            .into_inner()
+            .map(Expression::parse)
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .tuples()
+            .collect_vec();
+
+        if ranges.len() == 1 {
+            let (first, second) = ranges.remove(0);
+            Ok(ExpressionOrRange::Range {
+                is_random,
+                first,
+                second,
+            })
+        } else {
+            Ok(ExpressionOrRange::Compound { is_random, ranges })
+        }
+    }
+
     pub fn parse(next: Pair<Rule>) -> Result<Self, String> {
         let rule = next.as_rule();
 
@@ -71,29 +120,10 @@ impl ExpressionOrRange {
                     let r = ExpressionOrRange::Exp(exp);
                     Ok(r)
             },
-            Rule::range => {
-                let mut inner2 = next.into_inner();
-                let first = Expression::parse(inner2.next().unwrap())?;
-                //let dots = inner2.next();
-                let second = Expression::parse(inner2.next().unwrap())?;
-                let r = ExpressionOrRange::Range {
-                    is_random: false,
-                    first,
-                    second,
-                };
-                Ok(r)
-            }
+            Rule::range => Self::parse_range(next, false),
             Rule::range_random => {
                 let mut inner2 = next.into_inner();
-                let first = Expression::parse(inner2.next().unwrap())?;
-                //let dots = inner2.next();
-                let second = Expression::parse(inner2.next().unwrap())?;
-                let r = ExpressionOrRange::Range {
-                    is_random: true,
-                    first,
-                    second,
-                };
-                Ok(r)
+                Self::parse_range(inner2.next().unwrap(), true)
             }
 
             Rule::expression_or_range => {
@@ -202,13 +232,7 @@ impl Expression {
             Expression::Unary { operator, operand } => {
                 let val_or_range = operand.try_get_value(grammar, context, rng)?;
 
-                match val_or_range {
-                    ValueOrRange::Value(v) => Ok(ValueOrRange::Value(operator.apply(v))),
-                    ValueOrRange::Range { start, end } => Ok(ValueOrRange::Range {
-                        start: operator.apply(start),
-                        end: operator.apply(end),
-                    }),
-                }
+                Ok(operator.apply_range(val_or_range))
             }
 
             Expression::PropertyAccess { property } => Ok(property.get(context)),
@@ -0,0 +1,117 @@
+use crate::core::prelude::*;
+use serde::{Deserialize, Serialize};
+
+///The `canvas` directive, which declares the area of the plane that is drawn
+#[derive(PartialEq, Clone, Serialize, Deserialize)]
+pub struct CanvasDirective {
+    ///Fit the canvas around the shapes drawn instead of using its properties
+    pub fit: bool,
+    ///`x` and `y` give the centre of the canvas and `p`, `w` and `l` its half width and half height, as for a square
+    pub properties: Vec<TempProperty>,
+}
+
+///The area of the plane which is drawn, and the colour behind the shapes
+#[derive(PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Frame {
+    pub bounds: Bounds,
+    pub background: Option<Rgba>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self {
+            bounds: Bounds::CANVAS,
+            background: None,
+        }
+    }
+}
+
+impl Frame {
+    ///Evaluate the directives of a grammar with its current variables
+    pub fn from_directives(
+        canvas: Option<&[CompiledProperty]>,
+        background: Option<&[CompiledProperty]>,
+        variables: &[f32],
+    ) -> Self {
+        let evaluate = |properties: &[CompiledProperty]| {
+            let mut evaluated = NodeProperties::default_initial();
+            for property in properties {
+                //Properties which read properties are rejected when compiling
+                if let Some(value) = property.value.evaluate_static(variables) {
+                    property.key.set(&mut evaluated, value);
+                }
+            }
+            evaluated
+        };
+
+        let bounds = match canvas {
+            Some(properties) => {
+                let canvas = evaluate(properties);
+                let p = canvas.p.value();
+                Bounds::around(
+                    canvas.x.value().into(),
+                    canvas.y.value().into(),
+                    (p * canvas.w.value()).abs(),
+                    (p * canvas.l.value()).abs(),
+                )
+            }
+            None => Bounds::CANVAS,
+        };
+        let background = background.map(|properties| Rgba::from_properties(&evaluate(properties)));
+        Self { bounds, background }
+    }
+}
+
+impl CompiledGrammar {
+    ///The area outside which nodes are culled while expanding
+    pub fn viewport(&self, settings: &ExpandSettings) -> Bounds {
+        if self.fit_canvas {
+            Bounds::INFINITE
+        } else if self.canvas.is_some() {
+            self.frame.bounds
+        } else {
+            settings.viewport
+        }
+    }
+
+    ///The frame declared by the directives, with the current values of the variables
+    pub fn compute_frame(&self) -> Frame {
+        Frame::from_directives(
+            self.canvas.as_deref(),
+            self.background.as_deref(),
+            &self.variables,
+        )
+    }
+}
+
+impl NodeTree {
+    ///The area this tree is drawn in, which is fitted around its shapes if the grammar asks for it
+    pub fn frame(&self, grammar: &CompiledGrammar) -> Frame {
+        if !grammar.fit_canvas {
+            return grammar.frame;
+        }
+
+        let bounds = self
+            .leaves_in_draw_order()
+            .into_iter()
+            .map(|index| &self.nodes[index])
+            .filter_map(
+                |node| match grammar.get_invocation(node.invocation).method {
+                    CompiledMethod::Primitive(primitive) => {
+                        Some(primitive.get_bounds(&node.absolute_properties))
+                    }
+                    CompiledMethod::Rule(_) | CompiledMethod::Root => None,
+                },
+            )
+            .filter(|bounds| bounds.width().is_finite() && bounds.height().is_finite())
+            .reduce(Bounds::union);
+
+        Frame {
+            //An empty drawing shows the declared canvas
+            bounds: bounds
+                .filter(|b| b.width() > 0.0 && b.height() > 0.0)
+                .unwrap_or(grammar.frame.bounds),
+            background: grammar.frame.background,
+        }
+    }
+}
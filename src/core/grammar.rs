@@ -14,6 +14,15 @@ pub struct Grammar {
     pub top_level: Vec<Invocation>,
     pub defs: BTreeMap<String, f32>,
     pub rules: BTreeMap<String, UserRule>,
+    ///The `canvas` directive, if there is one
+    #[serde(default)]
+    pub canvas: Option<CanvasDirective>,
+    ///The properties of the `background` directive, if there is one
+    #[serde(default)]
+    pub background: Option<Vec<TempProperty>>,
+    ///The `sym` directive of the whole drawing, if there is one
+    #[serde(default)]
+    pub symmetry: Option<Symmetry>,
 }
 
 impl Grammar {
@@ -24,7 +33,14 @@ impl Grammar {
             .flat_map(|z| z.cases.iter().flat_map(|c| c.invocations.iter()));
         let all_invocations = self.top_level.iter().chain(rule_invocations);
 
-        let all_properties = all_invocations.flat_map(|i| i.properties.iter());
+        let directive_properties = self
+            .canvas
+            .iter()
+            .flat_map(|c| c.properties.iter())
+            .chain(self.background.iter().flatten());
+        let all_properties = all_invocations
+            .flat_map(|i| i.properties.iter())
+            .chain(directive_properties);
 
         let prob_properties = self.rules.values().flat_map(|r| {
             r.cases.iter().map(|c| c.probability.clone()).flat_map(|p| {
@@ -54,49 +70,81 @@ impl Grammar {
             })
             .collect_vec()
     }
+}
 
-    pub fn override_defs(&mut self, new_defs: &BTreeMap<String, f32>) {
-        for (key, val) in new_defs {
-            self.defs.insert(key.to_ascii_lowercase(), *val);
+///Why expansion stopped before the grammar was fully expanded
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum TruncationReason {
+    MaxNodes,
+    MaxSteps,
+    MaxTime,
+}
+
+///Why a node was culled
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum CullReason {
+    ///Its alpha is below `min_a`
+    Transparent,
+    ///Its size is below `min_p`
+    TooSmall,
+    ///Its depth is above `max_depth`
+    TooDeep,
+    ///Nothing it could draw is in the viewport
+    OutOfBounds,
+    ///It is painted over by a later opaque shape
+    Occluded,
+}
+
+///The number of nodes culled for each reason
+#[derive(Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct CullCounts {
+    pub transparent: usize,
+    pub too_small: usize,
+    pub too_deep: usize,
+    pub out_of_bounds: usize,
+    pub occluded: usize,
+}
+
+impl CullCounts {
+    pub fn add(&mut self, reason: CullReason) {
+        match reason {
+            CullReason::Transparent => self.transparent += 1,
+            CullReason::TooSmall => self.too_small += 1,
+            CullReason::TooDeep => self.too_deep += 1,
+            CullReason::OutOfBounds => self.out_of_bounds += 1,
+            CullReason::Occluded => self.occluded += 1,
         }
     }
 
-    pub fn expand(&self, settings: &ExpandSettings, rng: &mut StdRng) -> Node {
-        let mut current = ExpandStatistics::default();
-        let nodes = self
-            .top_level
-            .iter()
-            .map(|i| i.to_node(&NodeProperties::default_initial(), self, rng))
-            .collect_vec();
-
-        let mut root = Node {
-            invocation: Invocation {
-                method: Method::Root,
-                properties: Default::default(),
-            },
-            absolute_properties: NodeProperties::default_initial(),
-            children: Some(nodes),
-        };
-        loop {
-            let changes = root.expand_once(settings, self, rng);
-
-            current = current + &changes;
-            if changes.new_nodes == 0 {
-                break;
-            }
-            if current.new_nodes >= settings.max_nodes {
-                break;
-            }
-        }
+    pub fn total(&self) -> usize {
+        self.transparent + self.too_small + self.too_deep + self.out_of_bounds + self.occluded
+    }
+}
+
+impl std::ops::Add<&CullCounts> for CullCounts {
+    type Output = CullCounts;
 
-        root
+    fn add(self, rhs: &CullCounts) -> Self::Output {
+        Self {
+            transparent: self.transparent + rhs.transparent,
+            too_small: self.too_small + rhs.too_small,
+            too_deep: self.too_deep + rhs.too_deep,
+            out_of_bounds: self.out_of_bounds + rhs.out_of_bounds,
+            occluded: self.occluded + rhs.occluded,
+        }
     }
 }
 
-#[derive(Default, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct ExpandStatistics {
     pub new_nodes: usize,
     pub nodes_culled: usize,
+    ///The nodes culled, by reason
+    pub culled: CullCounts,
+    ///Set if expansion stopped because a budget ran out
+    pub truncated: Option<TruncationReason>,
+    ///The case chosen, when these are the statistics for expanding a single rule node
+    pub case: Option<usize>,
 }
 
 impl std::ops::Add<&ExpandStatistics> for ExpandStatistics {
@@ -106,12 +154,13 @@ impl std::ops::Add<&ExpandStatistics> for ExpandStatistics {
         Self {
             new_nodes: self.new_nodes + rhs.new_nodes,
             nodes_culled: self.nodes_culled + rhs.nodes_culled,
+            culled: self.culled + &rhs.culled,
+            truncated: self.truncated.or(rhs.truncated),
+            case: None,
         }
     }
 }
 
-
-
 impl Default for ExpandSettings {
     fn default() -> Self {
         Self {
@@ -119,6 +168,11 @@ impl Default for ExpandSettings {
             max_depth: 20,
             min_a: 0.001,
             min_p: 0.001,
+            max_steps: None,
+            max_milliseconds: None,
+            viewport: Bounds::CANVAS,
+            cull_margin: default_cull_margin(),
+            occlusion_culling: false,
         }
     }
 }
@@ -0,0 +1,257 @@
+use std::fmt::Write;
+
+use crate::core::prelude::*;
+use rand::{prelude::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+///How tall each extruded shape is
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum MeshHeight {
+    ///The absolute value of a property of each shape, multiplied by a scale, in millimetres
+    Property { key: PropertyKey, scale: f32 },
+    ///An expression such as `?v mul 5 add 1`, evaluated with the absolute properties of each shape, in millimetres
+    Expression(String),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum MeshFormat {
+    ///Binary stl
+    Stl,
+    ///Wavefront obj
+    Obj,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct MeshSettings {
+    ///The width of the canvas in millimetres
+    pub size: f32,
+    ///The thickness of a plate under the whole canvas, in millimetres, or zero for no plate
+    pub base: f32,
+    pub height: MeshHeight,
+    ///The largest distance, in millimetres, between a curve and the lines drawn in its place
+    pub tolerance: f32,
+}
+
+impl Default for MeshSettings {
+    fn default() -> Self {
+        Self {
+            size: 100.0,
+            base: 2.0,
+            //Each level of nesting stands a millimetre above its parent
+            height: MeshHeight::Property {
+                key: PropertyKey::D,
+                scale: 1.0,
+            },
+            tolerance: 0.05,
+        }
+    }
+}
+
+///A triangle mesh in millimetres, with z pointing up.
+///Triangles are wound anticlockwise when seen from outside.
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<[f32; 3]>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl Mesh {
+    ///Add a prism whose bottom and top are this convex polygon
+    pub fn add_prism(&mut self, outline: &[(f32, f32)], bottom: f32, top: f32) {
+        if outline.len() < 3 || top.is_nan() || top <= bottom {
+            return;
+        }
+        let signed_area: f32 = outline
+            .iter()
+            .zip(outline.iter().cycle().skip(1))
+            .map(|(a, b)| a.0 * b.1 - b.0 * a.1)
+            .sum();
+        let mut outline = outline.to_vec();
+        if signed_area < 0.0 {
+            outline.reverse();
+        }
+
+        let first = self.vertices.len() as u32;
+        let count = outline.len() as u32;
+        for z in [bottom, top] {
+            self.vertices
+                .extend(outline.iter().map(|(x, y)| [*x, *y, z]));
+        }
+        //The ends are fans, as the outline is convex
+        for i in 1..count - 1 {
+            self.triangles.push([first, first + i + 1, first + i]);
+            self.triangles
+                .push([first + count, first + count + i, first + count + i + 1]);
+        }
+        for i in 0..count {
+            let next = (i + 1) % count;
+            self.triangles
+                .push([first + i, first + next, first + count + next]);
+            self.triangles
+                .push([first + i, first + count + next, first + count + i]);
+        }
+    }
+
+    ///The unit normal of a triangle, following the right hand rule
+    fn normal(&self, [a, b, c]: [u32; 3]) -> [f32; 3] {
+        let [a, b, c] = [a, b, c].map(|i| self.vertices[i as usize]);
+        let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+        let v = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+        let n = [
+            u[1] * v[2] - u[2] * v[1],
+            u[2] * v[0] - u[0] * v[2],
+            u[0] * v[1] - u[1] * v[0],
+        ];
+        let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+        if length > 0.0 {
+            n.map(|x| x / length)
+        } else {
+            [0.0; 3]
+        }
+    }
+
+    pub fn to_stl(&self) -> Vec<u8> {
+        let mut stl = Vec::with_capacity(84 + 50 * self.triangles.len());
+        stl.extend_from_slice(b"convext");
+        stl.resize(80, 0);
+        stl.extend_from_slice(&(self.triangles.len() as u32).to_le_bytes());
+
+        for triangle in self.triangles.iter() {
+            let points = triangle.map(|i| self.vertices[i as usize]);
+            for v in std::iter::once(self.normal(*triangle)).chain(points) {
+                for x in v {
+                    stl.extend_from_slice(&x.to_le_bytes());
+                }
+            }
+            //No attributes
+            stl.extend_from_slice(&[0, 0]);
+        }
+        stl
+    }
+
+    pub fn to_obj(&self) -> String {
+        let mut obj = String::new();
+        //Writing to a string cannot fail
+        let _ = (|| -> std::fmt::Result {
+            writeln!(obj, "# convext")?;
+            for [x, y, z] in self.vertices.iter() {
+                writeln!(obj, "v {x} {y} {z}")?;
+            }
+            //Obj indices start at one
+            for [a, b, c] in self.triangles.iter() {
+                writeln!(obj, "f {} {} {}", a + 1, b + 1, c + 1)?;
+            }
+            Ok(())
+        })();
+        obj
+    }
+
+    pub fn export(&self, format: MeshFormat) -> Vec<u8> {
+        match format {
+            MeshFormat::Stl => self.to_stl(),
+            MeshFormat::Obj => self.to_obj().into_bytes(),
+        }
+    }
+}
+
+///Extrudes every shape into a prism standing on the canvas
+struct MeshRenderer<'a> {
+    grammar: &'a CompiledGrammar,
+    settings: &'a MeshSettings,
+    ///The height of each prism
+    height: CompiledExpression,
+    canvas: Bounds,
+    ///How many millimetres there are to one unit of the canvas
+    scale: f32,
+    mesh: Mesh,
+}
+
+impl MeshRenderer<'_> {
+    fn to_millimetres(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        //Meshes have y going up so that they are not mirrored
+        (
+            (x - self.canvas.min_x) * self.scale,
+            (self.canvas.max_y - y) * self.scale,
+        )
+    }
+}
+
+impl Renderer for MeshRenderer<'_> {
+    type Output = Mesh;
+
+    fn begin(&mut self, frame: &Frame) {
+        self.canvas = frame.bounds;
+        self.scale = self.settings.size / frame.bounds.width();
+        let depth = frame.bounds.height() * self.scale;
+        self.mesh.add_prism(
+            &[
+                (0.0, 0.0),
+                (self.settings.size, 0.0),
+                (self.settings.size, depth),
+                (0.0, depth),
+            ],
+            -self.settings.base,
+            0.0,
+        );
+    }
+
+    fn draw_primitive(&mut self, shape: &RenderShape) {
+        let properties = &shape.node.absolute_properties;
+        //Random ranges in the expression are the same every time for each node
+        let mut rng = StdRng::seed_from_u64(shape.node.seed);
+        let top = self
+            .height
+            .evaluate(&self.grammar.variables, properties, &mut rng)
+            .value();
+        let outline = shape
+            .primitive
+            .outline(properties, self.settings.tolerance / self.scale)
+            .into_iter()
+            .map(|p| self.to_millimetres(p))
+            .collect::<Vec<_>>();
+        self.mesh.add_prism(&outline, 0.0, top);
+    }
+
+    fn finish(self) -> Self::Output {
+        self.mesh
+    }
+}
+
+impl NodeTree {
+    ///Extrude every shape into a prism standing on the canvas.
+    ///Fails if the height expression cannot be parsed.
+    pub fn to_mesh(
+        &self,
+        grammar: &CompiledGrammar,
+        settings: &MeshSettings,
+    ) -> Result<Mesh, String> {
+        let height = match &settings.height {
+            MeshHeight::Property { key, scale } => CompiledExpression {
+                ops: vec![
+                    Op::Property(*key),
+                    Op::Number(*scale),
+                    Op::Binary(BinaryOperator::Mul),
+                ],
+            },
+            MeshHeight::Expression(text) => {
+                let variables = grammar
+                    .variable_names
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| (name.clone(), VariableId(i)))
+                    .collect();
+                CompiledExpression::compile(&parse_expression(text)?, &variables)?
+            }
+        };
+
+        let renderer = MeshRenderer {
+            grammar,
+            settings,
+            height,
+            canvas: Bounds::CANVAS,
+            scale: 1.0,
+            mesh: Mesh::default(),
+        };
+        Ok(self.render(grammar, renderer))
+    }
+}
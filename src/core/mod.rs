@@ -12,6 +12,26 @@ mod unary_operator;
 mod node_properties;
 mod value_or_range;
 mod expand_settings;
+mod stream;
+mod compiled_grammar;
+mod expansion_cache;
+mod bounds;
+mod occlusion;
+mod profile;
+mod color;
+mod svg;
+mod raster;
+mod pdf;
+mod plotter;
+mod dxf;
+mod mesh;
+mod scene;
+mod canvas;
+mod renderer;
+mod frame;
+mod embed;
+mod tiling;
+mod symmetry;
 
 pub mod prelude {
     pub use crate::core::examples::*;
@@ -28,4 +48,24 @@ pub mod prelude {
     pub use crate::core::node_properties::*;
     pub use crate::core::value_or_range::*;
     pub use crate::core::expand_settings::*;
+    pub use crate::core::stream::*;
+    pub use crate::core::compiled_grammar::*;
+    pub use crate::core::expansion_cache::*;
+    pub use crate::core::bounds::*;
+    pub use crate::core::occlusion::*;
+    pub use crate::core::profile::*;
+    pub use crate::core::color::*;
+    pub use crate::core::svg::*;
+    pub use crate::core::raster::*;
+    pub use crate::core::pdf::*;
+    pub use crate::core::plotter::*;
+    pub use crate::core::dxf::*;
+    pub use crate::core::mesh::*;
+    pub use crate::core::scene::*;
+    pub use crate::core::canvas::*;
+    pub use crate::core::renderer::*;
+    pub use crate::core::frame::*;
+    pub use crate::core::embed::*;
+    pub use crate::core::tiling::*;
+    pub use crate::core::symmetry::*;
 }
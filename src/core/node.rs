@@ -1,4 +1,4 @@
-use std::{collections::BTreeMap, default, str::FromStr};
+use std::{collections::BTreeMap, default, ops::Range, str::FromStr};
 
 use crate::core::prelude::*;
 use itertools::Itertools;
@@ -10,107 +10,161 @@ use rand::prelude::StdRng;
 use serde::{Deserialize, Serialize};
 use yew::Properties;
 
-#[derive(PartialEq, PartialOrd, Clone)]
+#[derive(PartialEq, Clone)]
 pub struct Node {
-    pub invocation: Invocation,
+    pub invocation: InvocationId,
+    ///Where this node is relative to its parent
+    pub transform: RelativeTransform,
     pub absolute_properties: NodeProperties,
-    pub children: Option<Vec<Node>>,
+    ///Seeds the random choices made when this node is expanded
+    pub seed: u64,
+    ///The indices of this node's children in the tree
+    pub children: Option<Range<usize>>,
 }
 
 impl Node {
-    pub fn to_svg(&self, grammar: &Grammar, rng: &mut StdRng,) -> String {
-        let elements = self.to_svg_element(grammar, rng);
+    ///Derive the seed of a child from its parent's seed and its position among its siblings.
+    ///Seeds therefore depend only on a node's path from the root, not on the order nodes are expanded in.
+    pub fn child_seed(parent_seed: u64, index: usize) -> u64 {
+        //splitmix64
+        let mut z = parent_seed.wrapping_add((index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
 
-        format!(
-            "<svg viewbox=\"-1 -1 2 2\" width=\"100%\" height=\"100%\" > {} </svg>",
-            elements
-        )
+    ///Create this node's children, leaving out any which should be culled
+    pub fn get_children(
+        &self,
+        settings: &ExpandSettings,
+        grammar: &CompiledGrammar,
+        stats: &mut ExpandStatistics,
+    ) -> Vec<Node> {
+        let (case, children) = grammar.choose_children(self.invocation, &self.absolute_properties, self.seed);
+        stats.case = case;
+        children
+            .into_iter()
+            .filter(|child| {
+                if let Some(reason) = settings.should_cull(child, grammar) {
+                    stats.nodes_culled += 1;
+                    stats.culled.add(reason);
+                    false
+                } else {
+                    stats.new_nodes += 1;
+                    true
+                }
+            })
+            .collect_vec()
     }
+}
 
-    fn get_style(rp: &NodeProperties, rng: &mut StdRng) -> String {
-        let mut transform = "".to_string();
-        if rp.x != 0.0.into() || rp.y != 0.0.into() {
-            transform = format!("{} translate({x}px, {y}px) ", transform, x = rp.x.random_value(rng), y = rp.y.random_value(rng));
-        }
-        if rp.p != 1.0.into() {
-            transform = format!("{} scale({p}%) ", transform, p = rp.p.random_value(rng) * 100.0);
-        }
-        if (rp.r != 0.0.into()) {
-            transform = format!("{} rotate({r}deg)", transform, r = rp.r.random_value(rng));
-        }
-        if !transform.is_empty() {
-            format!("style=\"transform: {};\"", transform)
-        } else {
-            "".to_string()
+///An expanded grammar, stored as a flat arena of nodes. The root is always at index 0.
+#[derive(PartialEq, Clone)]
+pub struct NodeTree {
+    pub nodes: Vec<Node>,
+    pub statistics: ExpandStatistics,
+    pub profile: ExpansionProfile,
+}
+
+impl NodeTree {
+    pub const ROOT: usize = 0;
+
+    pub fn new(root: Node, top_level: Vec<Node>) -> Self {
+        let mut nodes = vec![root];
+        nodes.extend(top_level);
+        nodes[Self::ROOT].children = Some(1..nodes.len());
+
+        Self {
+            nodes,
+            statistics: Default::default(),
+            profile: Default::default(),
         }
     }
 
-    pub fn to_svg_element(&self, grammar: &Grammar, rng: &mut StdRng,) -> String {
-        let relative_properties = NodeProperties::from_temp(
-            &self.invocation.properties,
-            grammar,
-            &self.absolute_properties,
-            rng
-        );
-
-        if self.children.is_some() && !self.children.as_ref().unwrap().is_empty() {
-            let child_text = self
-                .children
-                .as_ref()
-                .unwrap()
-                .iter()
-                .map(|c| c.to_svg_element(grammar, rng))
-                .join("\r\n");
-
-            let style = Self::get_style(&relative_properties, rng);
-
-            format!(
-                "<g {style}>\r\n {child_text}\r\n </g>",
-                //no color
-                style = style,
-                child_text = child_text
-            )
-        } else {
-            match self.invocation.method {
-                Method::Root => "".to_string(),
-                Method::Primitive(p) => p.to_svg(&relative_properties, &self.absolute_properties, rng),
-                Method::Rule(_) => "".to_string(),
-            }
+    pub fn root(&self) -> &Node {
+        &self.nodes[Self::ROOT]
+    }
+
+    pub fn children(&self, node: &Node) -> &[Node] {
+        match &node.children {
+            Some(range) => &self.nodes[range.clone()],
+            None => &[],
         }
     }
 
+    ///Expand every node in the frontier, appending the new nodes to the end of the tree
     pub fn expand_once(
         &mut self,
+        frontier: Range<usize>,
         settings: &ExpandSettings,
-        grammar: &Grammar,
-        rng: &mut StdRng,
+        grammar: &CompiledGrammar,
+        budget: &mut ExpandBudget,
     ) -> ExpandStatistics {
-        let mut stats = ExpandStatistics::default();
+        self.expand_frontier(frontier, budget, |node| {
+            let mut stats = ExpandStatistics::default();
+            (node.get_children(settings, grammar, &mut stats), stats)
+        })
+        .into_iter()
+        .fold(ExpandStatistics::default(), |acc, s| acc + &s)
+    }
 
-        if self.children.is_some() {
-            for child in self.children.as_mut().unwrap().iter_mut() {
-                let child_stats = child.expand_once(settings, grammar, rng);
-                stats = stats + &child_stats;
-            }
-        } else {
-            let new_children = self
-                .invocation
-                .get_children(&self.absolute_properties, grammar, rng)
-                .into_iter()
-                .filter_map(|node| {
-                    if settings.should_cull(&node) {
-                        stats.nodes_culled += 1;
-                        None
-                    } else {
-                        stats.new_nodes += 1;
-                        Some(node)
+    ///Give nodes in the frontier the children returned by `get_children`, in order, until the budget runs out.
+    ///Returns the statistics for each node which was expanded.
+    pub fn expand_frontier<F>(
+        &mut self,
+        frontier: Range<usize>,
+        budget: &mut ExpandBudget,
+        get_children: F,
+    ) -> Vec<ExpandStatistics>
+    where
+        F: Fn(&Node) -> (Vec<Node>, ExpandStatistics) + Sync,
+    {
+        let mut node_stats = Vec::with_capacity(frontier.len());
+
+        //Expand in chunks so that little work is wasted when the budget runs out
+        for chunk in &frontier.chunks(FRONTIER_CHUNK_SIZE) {
+            let chunk = chunk.collect_vec();
+            let chunk_range = chunk[0]..(chunk[chunk.len() - 1] + 1);
+            let all_children = Self::get_frontier_children(&self.nodes[chunk_range], &get_children);
+
+            for (index, (new_children, stats)) in chunk.into_iter().zip(all_children) {
+                match budget.take(new_children, stats) {
+                    Some((new_children, stats)) => {
+                        let start = self.nodes.len();
+                        self.nodes.extend(new_children);
+                        self.nodes[index].children = Some(start..self.nodes.len());
+                        node_stats.push(stats);
                     }
-                })
-                .collect_vec();
+                    None => return node_stats,
+                }
+            }
+        }
+
+        node_stats
+    }
 
-            self.children = Some(new_children);
-        };
+    #[cfg(not(feature = "parallel"))]
+    fn get_frontier_children<F>(frontier: &[Node], get_children: &F) -> Vec<(Vec<Node>, ExpandStatistics)>
+    where
+        F: Fn(&Node) -> (Vec<Node>, ExpandStatistics) + Sync,
+    {
+        frontier.iter().map(get_children).collect_vec()
+    }
+
+    ///Expand the frontier on the rayon thread pool.
+    ///Each node's children depend only on the node itself, so the result is the same for any number of threads
+    #[cfg(feature = "parallel")]
+    fn get_frontier_children<F>(frontier: &[Node], get_children: &F) -> Vec<(Vec<Node>, ExpandStatistics)>
+    where
+        F: Fn(&Node) -> (Vec<Node>, ExpandStatistics) + Sync,
+    {
+        use rayon::prelude::*;
 
-        stats
+        frontier.par_iter().map(get_children).collect()
     }
 }
+
+#[cfg(not(feature = "parallel"))]
+const FRONTIER_CHUNK_SIZE: usize = 64;
+#[cfg(feature = "parallel")]
+const FRONTIER_CHUNK_SIZE: usize = 1024;
@@ -9,6 +9,34 @@ use rand::prelude::StdRng;
 use serde::{Deserialize, Serialize};
 
 
+///The position, scale and rotation of a node relative to its parent, with any ranges resolved.
+///This is all that is needed to place a node inside its parent's group.
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug)]
+pub struct RelativeTransform {
+    pub x: f32,
+    pub y: f32,
+    pub p: f32,
+    pub r: f32,
+}
+
+impl RelativeTransform {
+    pub const IDENTITY: RelativeTransform = RelativeTransform {
+        x: 0.0,
+        y: 0.0,
+        p: 1.0,
+        r: 0.0,
+    };
+
+    pub fn from_properties(properties: &NodeProperties) -> Self {
+        Self {
+            x: properties.x.value(),
+            y: properties.y.value(),
+            p: properties.p.value(),
+            r: properties.r.value(),
+        }
+    }
+}
+
 #[derive(PartialEq, PartialOrd, Clone)]
 pub struct NodeProperties {
     pub p: ValueOrRange,
@@ -17,39 +45,33 @@ pub struct NodeProperties {
     pub c: ValueOrRange,
     pub x: ValueOrRange,
     pub y: ValueOrRange,
+    ///The height of the node, which is only used when extruding to 3d
+    pub z: ValueOrRange,
     pub r: ValueOrRange,
     pub h: ValueOrRange,
     pub s: ValueOrRange,
     pub v: ValueOrRange,
     pub a: ValueOrRange,
     pub d: usize,
+    ///Whether this is reflected in its vertical axis, which reflects where its children are placed
+    pub mirrored: bool,
 }
 
 
 
 impl NodeProperties {
-    pub fn from_temp(
-        vector: &Vec<TempProperty>,
-        grammar: &Grammar,
-        context: &NodeProperties,
-        rng: &mut StdRng,
-    ) -> Self {
-        let mut properties = Self::default_additive();
-
-        for prop in vector {
-            let value = prop.value.try_get_value(grammar, context, rng).unwrap();
-            prop.key.set(&mut properties, value);
-        }
-
-        properties
-    }
-
     ///Make absolute child properties from the child relative propeties
     pub fn make_absolute(&self, child: &Self) -> Self {
+        //A mirrored parent reflects where its children are placed
+        let (child_x, child_r) = if self.mirrored {
+            (ValueOrRange::default() - child.x, ValueOrRange::default() - child.r)
+        } else {
+            (child.x, child.r)
+        };
         let x2 = self.p
-            * ((self.r.cos_degrees() * child.x) - (self.r.sin_degrees() * child.y));
+            * ((self.r.cos_degrees() * child_x) - (self.r.sin_degrees() * child.y));
         let y2 = self.p
-            * ((self.r.sin_degrees() * child.x) + (self.r.cos_degrees() * child.y));
+            * ((self.r.sin_degrees() * child_x) + (self.r.cos_degrees() * child.y));
 
         Self {
             p: (self.p * child.p).max(0.0),
@@ -58,12 +80,34 @@ impl NodeProperties {
             c: self.c + child.c.clamp(0.0, 1.0),
             x: self.x + x2,
             y: self.y + y2,
-            r: (self.r + child.r).mod360(),
+            z: self.z + self.p * child.z,
+            r: (self.r + child_r).mod360(),
             h: (self.h + child.h).mod360(),
             s: (self.s + child.s).clamp(0.0, 1.0),
             v: (self.v + child.v).clamp(0.0, 1.0),
             a: (self.a * child.a).clamp(0.0, 1.0),
             d: self.d + child.d,
+            mirrored: self.mirrored ^ child.mirrored,
+        }
+    }
+
+    ///These properties with a random value chosen from each range
+    pub fn sample(&self, rng: &mut StdRng) -> Self {
+        Self {
+            p: self.p.random_value(rng).into(),
+            l: self.l.random_value(rng).into(),
+            w: self.w.random_value(rng).into(),
+            c: self.c.random_value(rng).into(),
+            x: self.x.random_value(rng).into(),
+            y: self.y.random_value(rng).into(),
+            z: self.z.random_value(rng).into(),
+            r: self.r.random_value(rng).into(),
+            h: self.h.random_value(rng).into(),
+            s: self.s.random_value(rng).into(),
+            v: self.v.random_value(rng).into(),
+            a: self.a.random_value(rng).into(),
+            d: self.d,
+            mirrored: self.mirrored,
         }
     }
 
@@ -75,12 +119,14 @@ impl NodeProperties {
             c: Default::default(),
             x: Default::default(),
             y: Default::default(),
+            z: Default::default(),
             r: Default::default(),
             h: Default::default(),
             s: 1.0.into(),
             v: 0.0.into(),
             a: 1.0.into(),
             d: Default::default(),
+            mirrored: false,
         }
     }
 
@@ -92,12 +138,14 @@ impl NodeProperties {
             c: Default::default(),
             x: Default::default(),
             y: Default::default(),
+            z: Default::default(),
             r: Default::default(),
             h: Default::default(),
             s: 0.0.into(),
             v: 0.0.into(),
             a: 1.0.into(),
             d: 1,
+            mirrored: false,
         }
     }
 }
\ No newline at end of file
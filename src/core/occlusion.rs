@@ -0,0 +1,209 @@
+use std::collections::VecDeque;
+
+use crate::core::prelude::*;
+use itertools::Itertools;
+
+///A disc in absolute coordinates
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Disc {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+}
+
+impl Disc {
+    pub fn contains(&self, bounds: &Bounds) -> bool {
+        [
+            (bounds.min_x, bounds.min_y),
+            (bounds.min_x, bounds.max_y),
+            (bounds.max_x, bounds.min_y),
+            (bounds.max_x, bounds.max_y),
+        ]
+        .into_iter()
+        .all(|(x, y)| (x - self.x).hypot(y - self.y) <= self.radius)
+    }
+}
+
+impl Primitive {
+    ///A disc which this shape, drawn with these absolute properties, certainly paints over.
+    ///Returns `None` if the shape is not opaque or is not drawn in the same place every time.
+    pub fn inner_disc(&self, properties: &NodeProperties) -> Option<Disc> {
+        let value = |v: ValueOrRange| match v {
+            ValueOrRange::Value(v) => Some(v),
+            ValueOrRange::Range { .. } | ValueOrRange::Compound(_) => None,
+        };
+        if value(properties.a)? < 1.0 {
+            return None;
+        }
+
+        let p = value(properties.p)?.max(0.0);
+        let half_width = p * value(properties.w)?.abs();
+        let half_height = p * value(properties.l)?.abs();
+
+        //The centre and radius of the shape's incircle, when its half width and half height are 1
+        let (offset, radius) = match self {
+            Primitive::Circle | Primitive::Square => (0.0, 1.0),
+            Primitive::Polygon(sides) => (0.0, (std::f32::consts::PI / *sides as f32).cos()),
+            Primitive::RightTriangle => {
+                let radius = 2.0 / (1.0 + 5.0f32.sqrt());
+                (1.0 - radius, radius)
+            }
+        };
+
+        //The offset is along the shape's own y axis
+        let (sin, cos) = value(properties.r)?.to_radians().sin_cos();
+        Some(Disc {
+            x: value(properties.x)? - sin * offset * half_height,
+            y: value(properties.y)? + cos * offset * half_height,
+            //Leave room for rounding errors
+            radius: radius * half_width.min(half_height) * 0.999,
+        })
+    }
+}
+
+const GRID_SIZE: usize = 32;
+
+///Discs bucketed by the grid cells they overlap
+struct DiscGrid {
+    area: Bounds,
+    cells: Vec<Vec<Disc>>,
+}
+
+impl DiscGrid {
+    fn new(area: Bounds) -> Self {
+        Self {
+            area,
+            cells: vec![vec![]; GRID_SIZE * GRID_SIZE],
+        }
+    }
+
+    fn cell(&self, x: f32, y: f32) -> (usize, usize) {
+        let to_cell = |v: f32, min: f32, size: f32| {
+            if size > 0.0 {
+                (((v - min) / size * GRID_SIZE as f32).max(0.0) as usize).min(GRID_SIZE - 1)
+            } else {
+                0
+            }
+        };
+        (
+            to_cell(x, self.area.min_x, self.area.width()),
+            to_cell(y, self.area.min_y, self.area.height()),
+        )
+    }
+
+    fn insert(&mut self, disc: Disc) {
+        let (min_x, min_y) = self.cell(disc.x - disc.radius, disc.y - disc.radius);
+        let (max_x, max_y) = self.cell(disc.x + disc.radius, disc.y + disc.radius);
+        for cell_y in min_y..=max_y {
+            for cell_x in min_x..=max_x {
+                self.cells[cell_y * GRID_SIZE + cell_x].push(disc);
+            }
+        }
+    }
+
+    ///Whether any disc contains the bounds.
+    ///Such a disc must contain the centre of the bounds so only that cell is searched.
+    fn covers(&self, bounds: &Bounds) -> bool {
+        let (x, y) = self.cell(
+            (bounds.min_x + bounds.max_x) / 2.0,
+            (bounds.min_y + bounds.max_y) / 2.0,
+        );
+        self.cells[y * GRID_SIZE + x].iter().any(|d| d.contains(bounds))
+    }
+}
+
+impl NodeTree {
+    ///The indices of the nodes without children, in the order they are drawn
+    pub fn leaves_in_draw_order(&self) -> Vec<usize> {
+        let mut leaves = vec![];
+        let mut stack = vec![Self::ROOT];
+        while let Some(index) = stack.pop() {
+            match self.nodes[index].children.clone() {
+                Some(children) if !children.is_empty() => stack.extend(children.rev()),
+                _ => leaves.push(index),
+            }
+        }
+        leaves
+    }
+
+    ///Remove primitives which are completely painted over by a single later opaque primitive, and any groups left empty.
+    ///Coverage is tested conservatively so some hidden primitives may be kept.
+    ///Returns the number of nodes removed.
+    pub fn remove_occluded(&mut self, grammar: &CompiledGrammar) -> usize {
+        let primitives = self
+            .leaves_in_draw_order()
+            .into_iter()
+            .filter_map(|index| match grammar.get_invocation(self.nodes[index].invocation).method {
+                CompiledMethod::Primitive(primitive) => Some((index, primitive)),
+                CompiledMethod::Rule(_) | CompiledMethod::Root => None,
+            })
+            .collect_vec();
+        let area = match primitives
+            .iter()
+            .map(|(index, primitive)| primitive.get_bounds(&self.nodes[*index].absolute_properties))
+            .reduce(Bounds::union)
+        {
+            Some(area) => area,
+            None => return 0,
+        };
+
+        let mut keep = vec![true; self.nodes.len()];
+        let mut grid = DiscGrid::new(area);
+        for (index, primitive) in primitives.into_iter().rev() {
+            let properties = &self.nodes[index].absolute_properties;
+            if grid.covers(&primitive.get_bounds(properties)) {
+                keep[index] = false;
+            } else if let Some(disc) = primitive.inner_disc(properties) {
+                grid.insert(disc);
+            }
+        }
+
+        //Children always come after their parents so this visits every child before its parent
+        for index in (0..self.nodes.len()).rev() {
+            if let Some(children) = self.nodes[index].children.clone() {
+                if !children.is_empty() && children.clone().all(|c| !keep[c]) {
+                    keep[index] = false;
+                }
+            }
+        }
+        keep[Self::ROOT] = true;
+
+        let removed = keep.iter().filter(|k| !**k).count();
+        if removed > 0 {
+            self.retain(&keep);
+        }
+        removed
+    }
+
+    ///Remove occluded nodes with `remove_occluded` and count them in the statistics of this tree
+    pub fn cull_occluded(&mut self, grammar: &CompiledGrammar) {
+        let occluded = self.remove_occluded(grammar);
+        self.statistics.nodes_culled += occluded;
+        self.statistics.culled.occluded += occluded;
+    }
+
+    ///Rebuild the tree with only the nodes to keep, in the same order
+    fn retain(&mut self, keep: &[bool]) {
+        let mut nodes = vec![Node {
+            children: None,
+            ..self.nodes[Self::ROOT].clone()
+        }];
+        let mut queue = VecDeque::from([(Self::ROOT, Self::ROOT)]);
+
+        while let Some((old, new)) = queue.pop_front() {
+            if let Some(children) = self.nodes[old].children.clone() {
+                let start = nodes.len();
+                for child in children.filter(|c| keep[*c]) {
+                    queue.push_back((child, nodes.len()));
+                    nodes.push(Node {
+                        children: None,
+                        ..self.nodes[child].clone()
+                    });
+                }
+                nodes[new].children = Some(start..nodes.len());
+            }
+        }
+
+        self.nodes = nodes;
+    }
+}
@@ -22,6 +22,9 @@ pub fn parse(input: &str) -> Result<Grammar, String> {
     let mut rules = BTreeMap::<String, UserRule>::default();
 
     let mut top_level = Vec::<Invocation>::default();
+    let mut canvas: Option<CanvasDirective> = None;
+    let mut background: Option<Vec<TempProperty>> = None;
+    let mut symmetry: Option<Symmetry> = None;
 
     for pair in file.into_inner() {
         match pair.as_rule() {
@@ -100,6 +103,43 @@ pub fn parse(input: &str) -> Result<Grammar, String> {
                             return Err(format!("Variable '{}' defined more than once", name));
                         }
                     }
+                    Rule::canvas => {
+                        if canvas.is_some() {
+                            return Err("Canvas defined more than once".to_string());
+                        }
+                        let mut directive = CanvasDirective {
+                            fit: false,
+                            properties: vec![],
+                        };
+                        for p in statement.into_inner() {
+                            match p.as_rule() {
+                                Rule::keyword_canvas => (),
+                                Rule::keyword_fit => directive.fit = true,
+                                Rule::property => {
+                                    directive.properties.push(TempProperty::try_parse(&mut p.into_inner())?)
+                                }
+                                _ => unreachable!(),
+                            }
+                        }
+                        canvas = Some(directive);
+                    }
+                    Rule::background => {
+                        if background.is_some() {
+                            return Err("Background defined more than once".to_string());
+                        }
+                        let mut properties = Vec::<TempProperty>::new();
+                        for p in statement.into_inner().skip(1) {
+                            properties.push(TempProperty::try_parse(&mut p.into_inner())?);
+                        }
+                        background = Some(properties);
+                    }
+                    Rule::symmetry => {
+                        //A `sym` straight after an invocation is parsed as part of it
+                        if symmetry.is_some() {
+                            return Err("Symmetry defined more than once".to_string());
+                        }
+                        symmetry = Some(Symmetry::try_parse(statement)?);
+                    }
 
                     _ => unreachable!(),
                 }
@@ -141,5 +181,20 @@ pub fn parse(input: &str) -> Result<Grammar, String> {
         defs,
         rules,
         top_level,
+        canvas,
+        background,
+        symmetry,
     })
 }
+
+///Parse a single expression, such as `?v mul 5`
+pub fn parse_expression(input: &str) -> Result<Expression, String> {
+    let input = input.trim();
+    let mut pairs = ConvextParser::parse(Rule::expression, input).map_err(|e| e.to_string())?;
+    let expression = pairs.next().unwrap();
+    let rest = &input[expression.as_str().len()..];
+    if !rest.is_empty() {
+        return Err(format!("Unexpected '{}' after expression", rest));
+    }
+    Expression::parse(expression)
+}
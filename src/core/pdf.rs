@@ -0,0 +1,250 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::core::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const POINTS_PER_MILLIMETRE: f32 = 72.0 / 25.4;
+
+///How far along the tangents to put the control points of a cubic bezier approximating a quarter circle
+const KAPPA: f32 = 0.552_284_8;
+
+///The physical size of a pdf page
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PdfSettings {
+    ///The width of the page in millimetres
+    pub page_width: f32,
+    ///The height of the page in millimetres
+    pub page_height: f32,
+    ///The space left empty on every side of the page, in millimetres
+    pub margin: f32,
+}
+
+impl Default for PdfSettings {
+    fn default() -> Self {
+        Self::A4
+    }
+}
+
+impl PdfSettings {
+    pub const A4: PdfSettings = PdfSettings {
+        page_width: 210.0,
+        page_height: 297.0,
+        margin: 10.0,
+    };
+
+    pub const LETTER: PdfSettings = PdfSettings {
+        page_width: 215.9,
+        page_height: 279.4,
+        margin: 10.0,
+    };
+}
+
+impl Primitive {
+    ///Write the path of this shape, when its centre is at the origin and its half width and half height are 1, to a pdf content stream
+    fn write_unit_pdf_path(&self, corner_x: f32, corner_y: f32, content: &mut String) -> std::fmt::Result {
+        let k = KAPPA;
+        match self {
+            Primitive::Circle => writeln!(
+                content,
+                "1 0 m 1 {k} {k} 1 0 1 c -{k} 1 -1 {k} -1 0 c -1 -{k} -{k} -1 0 -1 c {k} -1 1 -{k} 1 0 c h"
+            ),
+            Primitive::Square if corner_x > 0.0 && corner_y > 0.0 => {
+                let (x0, x1) = (corner_x - 1.0, 1.0 - corner_x);
+                let (y0, y1) = (corner_y - 1.0, 1.0 - corner_y);
+                let (kx, ky) = (k * corner_x, k * corner_y);
+                writeln!(content, "{x0} -1 m {x1} -1 l")?;
+                writeln!(content, "{} -1 1 {} 1 {y0} c 1 {y1} l", x1 + kx, y0 - ky)?;
+                writeln!(content, "1 {} {} 1 {x1} 1 c {x0} 1 l", y1 + ky, x1 + kx)?;
+                writeln!(content, "{} 1 -1 {} -1 {y1} c -1 {y0} l", x0 - kx, y1 + ky)?;
+                writeln!(content, "-1 {} {} -1 {x0} -1 c h", y0 - ky, x0 - kx)
+            }
+            Primitive::Square => writeln!(content, "-1 -1 2 2 re"),
+            Primitive::RightTriangle => writeln!(content, "0 -1 m 1 1 l -1 1 l h"),
+            Primitive::Polygon(sides) => {
+                for (i, (x, y)) in Self::get_polygon_points(*sides).enumerate() {
+                    write!(content, "{x} {y} {} ", if i == 0 { "m" } else { "l" })?;
+                }
+                writeln!(content, "h")
+            }
+        }
+    }
+}
+
+///Draws a single pdf page.
+///The canvas is made as large as it can be inside the margins and centred on the page.
+struct PdfRenderer {
+    page_width: f32,
+    page_height: f32,
+    margin: f32,
+    content: String,
+    ///Opacities are set with graphics states, see `write_fill`
+    opacities: BTreeMap<u32, String>,
+}
+
+impl PdfRenderer {
+    fn new(settings: &PdfSettings) -> Self {
+        Self {
+            page_width: settings.page_width * POINTS_PER_MILLIMETRE,
+            page_height: settings.page_height * POINTS_PER_MILLIMETRE,
+            margin: settings.margin * POINTS_PER_MILLIMETRE,
+            content: String::new(),
+            opacities: BTreeMap::new(),
+        }
+    }
+
+    fn write_frame(&mut self, frame: &Frame) -> std::fmt::Result {
+        let margin = self.margin;
+        let inner_width = (self.page_width - 2.0 * margin).max(0.0);
+        let inner_height = (self.page_height - 2.0 * margin).max(0.0);
+        let scale = frame.bounds.scale_to_fit(inner_width, inner_height);
+        let (centre_x, centre_y) = frame.bounds.centre();
+        let content = &mut self.content;
+
+        writeln!(content, "q {margin} {margin} {inner_width} {inner_height} re W n")?;
+        //Pdf coordinates go up the page
+        writeln!(
+            content,
+            "{scale} 0 0 -{scale} {} {} cm",
+            self.page_width / 2.0 - centre_x * scale,
+            self.page_height / 2.0 + centre_y * scale
+        )?;
+
+        if let Some(background) = frame.background {
+            write!(content, "q ")?;
+            write_fill(content, &mut self.opacities, &background)?;
+            let bounds = frame.bounds;
+            writeln!(
+                content,
+                "{} {} {} {} re f Q",
+                bounds.min_x,
+                bounds.min_y,
+                bounds.width(),
+                bounds.height()
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_shape(&mut self, shape: &RenderShape) -> std::fmt::Result {
+        let transform = &shape.transform;
+        let (half_width, half_height) = (transform.half_width, transform.half_height);
+        let (x, y) = (transform.x, transform.y);
+        let is_drawn = [half_width, half_height, x, y].iter().all(|v| v.is_finite());
+        if shape.color.a <= 0.0 || half_width == 0.0 || half_height == 0.0 || !is_drawn {
+            return Ok(());
+        }
+        let content = &mut self.content;
+
+        write!(content, "q ")?;
+        write_fill(content, &mut self.opacities, &shape.color)?;
+
+        //Shapes rotate about their own centre
+        let (sin, cos) = transform.rotation.to_radians().sin_cos();
+        writeln!(
+            content,
+            "{} {} {} {} {} {} cm",
+            half_width * cos,
+            half_width * sin,
+            -half_height * sin,
+            half_height * cos,
+            x,
+            y
+        )?;
+
+        //Like svg, each radius of the corners is at most the half size along its own axis
+        let corner_x = transform.corner.min(half_width.abs());
+        let corner_y = transform.corner.min(half_height.abs());
+        shape
+            .primitive
+            .write_unit_pdf_path(corner_x / half_width.abs(), corner_y / half_height.abs(), content)?;
+        writeln!(content, "f Q")
+    }
+}
+
+impl Renderer for PdfRenderer {
+    type Output = Vec<u8>;
+
+    fn begin(&mut self, frame: &Frame) {
+        //Writing to a string cannot fail
+        let _ = self.write_frame(frame);
+    }
+
+    fn draw_primitive(&mut self, shape: &RenderShape) {
+        let _ = self.write_shape(shape);
+    }
+
+    fn finish(mut self) -> Self::Output {
+        self.content.push_str("Q\n");
+        let (page_width, page_height) = (self.page_width, self.page_height);
+
+        let graphics_states = self
+            .opacities
+            .iter()
+            .map(|(key, name)| format!("/{name} << /Type /ExtGState /ca {} >>", *key as f32 / 1000.0))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let stream = miniz_oxide::deflate::compress_to_vec_zlib(self.content.as_bytes(), 6);
+        let objects: Vec<Vec<u8>> = vec![
+            b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+            b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec(),
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {page_width} {page_height}] /Resources << /ExtGState << {graphics_states} >> >> /Contents 4 0 R >>"
+            )
+            .into_bytes(),
+            [
+                format!("<< /Length {} /Filter /FlateDecode >>\nstream\n", stream.len()).as_bytes(),
+                &stream,
+                b"\nendstream",
+            ]
+            .concat(),
+        ];
+
+        let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+        let mut offsets = vec![];
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+            pdf.extend_from_slice(object);
+            pdf.extend_from_slice(b"\nendobj\n");
+        }
+
+        let cross_reference = pdf.len();
+        let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1);
+        for offset in offsets {
+            trailer.push_str(&format!("{offset:010} 00000 n \n"));
+        }
+        trailer.push_str(&format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{cross_reference}\n%%EOF\n",
+            objects.len() + 1
+        ));
+        pdf.extend_from_slice(trailer.as_bytes());
+        pdf
+    }
+}
+
+impl NodeTree {
+    ///Draw this tree on a single pdf page.
+    ///The canvas is made as large as it can be inside the margins and centred on the page.
+    pub fn to_pdf(&self, grammar: &CompiledGrammar, settings: &PdfSettings) -> Vec<u8> {
+        self.render(grammar, PdfRenderer::new(settings))
+    }
+}
+
+///Set the fill colour and opacity of a content stream.
+///Opacities are set with graphics states, named by their opacity in thousandths.
+fn write_fill(content: &mut String, opacities: &mut BTreeMap<u32, String>, color: &Rgba) -> std::fmt::Result {
+    if color.a < 1.0 {
+        let key = (color.a * 1000.0).round() as u32;
+        let count = opacities.len();
+        let name = opacities.entry(key).or_insert_with(|| format!("A{count}"));
+        write!(content, "/{name} gs ")?;
+    }
+    writeln!(
+        content,
+        "{} {} {} rg",
+        color.r as f32 / 255.0,
+        color.g as f32 / 255.0,
+        color.b as f32 / 255.0
+    )
+}
@@ -0,0 +1,573 @@
+use std::fmt::Write;
+
+use crate::core::prelude::*;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+pub const INKSCAPE_NAMESPACE: &str = "http://www.inkscape.org/namespaces/inkscape";
+
+///HPGL plotter units are 0.025mm
+const HPGL_UNITS_PER_MILLIMETRE: f32 = 40.0;
+
+///How to turn a tree into pen strokes
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PlotterSettings {
+    ///The width of the paper in millimetres
+    pub page_width: f32,
+    ///The height of the paper in millimetres
+    pub page_height: f32,
+    ///The space left empty on every side of the paper, in millimetres. Nothing is drawn outside it.
+    pub margin: f32,
+    ///The largest distance, in millimetres, between a curve and the lines drawn in its place
+    pub tolerance: f32,
+    ///If set, shapes are filled with parallel lines this many millimetres apart where they are darkest, and further apart where they are lighter
+    pub hatch_spacing: Option<f32>,
+    ///The number of pens. Shapes are drawn with the pen for their hue.
+    pub hue_layers: usize,
+    ///Reorder the paths of each layer to reduce how far the pen moves while it is up
+    pub optimise_travel: bool,
+}
+
+impl Default for PlotterSettings {
+    fn default() -> Self {
+        Self {
+            page_width: 210.0,
+            page_height: 297.0,
+            margin: 10.0,
+            tolerance: 0.1,
+            hatch_spacing: None,
+            hue_layers: 1,
+            optimise_travel: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum PlotterFormat {
+    Hpgl,
+    GCode,
+    Svg,
+}
+
+///A line through some points, in millimetres from the top left of the paper
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Polyline {
+    pub points: Vec<(f32, f32)>,
+    ///Whether the line goes back to its first point at the end
+    pub closed: bool,
+}
+
+impl Polyline {
+    pub fn start(&self) -> (f32, f32) {
+        self.points[0]
+    }
+
+    pub fn end(&self) -> (f32, f32) {
+        if self.closed {
+            self.points[0]
+        } else {
+            self.points[self.points.len() - 1]
+        }
+    }
+
+    ///The points the pen goes through, in order
+    pub fn pen_points(&self) -> impl Iterator<Item = &(f32, f32)> + '_ {
+        self.points.iter().chain(self.points.first().filter(|_| self.closed))
+    }
+}
+
+///The paths drawn with one pen
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PlotterLayer {
+    ///The hue at the middle of the range of hues drawn with this pen
+    pub hue: f32,
+    pub paths: Vec<Polyline>,
+}
+
+///Pen strokes on a piece of paper
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Plot {
+    pub page_width: f32,
+    pub page_height: f32,
+    pub layers: Vec<PlotterLayer>,
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+///The ends of some paths, in square cells so that the nearest end to a point can be found without looking at every path
+struct EndpointGrid {
+    min: (f32, f32),
+    cell_size: f32,
+    columns: usize,
+    rows: usize,
+    ///The ends in each cell, as the index of their path and whether the path must be reversed to start there
+    cells: Vec<Vec<(usize, bool)>>,
+    ///How many paths were in the grid when it was made
+    paths: usize,
+}
+
+impl EndpointGrid {
+    fn new(paths: &[Option<Polyline>]) -> Self {
+        let ends = Self::ends(paths).collect_vec();
+        let (min_x, max_x) = ends.iter().map(|e| e.2 .0).minmax().into_option().unwrap_or((0.0, 0.0));
+        let (min_y, max_y) = ends.iter().map(|e| e.2 .1).minmax().into_option().unwrap_or((0.0, 0.0));
+        let count = paths.iter().flatten().count();
+        //About one path in each cell
+        let side = ((count as f32).sqrt().ceil() as usize).max(1);
+        let cell_size = (max_x - min_x).max(max_y - min_y) / side as f32;
+        let cell_size = if cell_size > 0.0 && cell_size.is_finite() { cell_size } else { 1.0 };
+        let cells_across = |span: f32| ((span / cell_size) as usize + 1).min(side);
+        let (columns, rows) = (cells_across(max_x - min_x), cells_across(max_y - min_y));
+
+        let mut grid = Self {
+            min: (min_x, min_y),
+            cell_size,
+            columns,
+            rows,
+            cells: vec![vec![]; columns * rows],
+            paths: count,
+        };
+        for (index, reverse, point) in ends {
+            let (x, y) = grid.cell(point);
+            grid.cells[y * columns + x].push((index, reverse));
+        }
+        grid
+    }
+
+    ///Where each path can start, as its index, whether it is reversed and the point it starts at
+    fn ends(paths: &[Option<Polyline>]) -> impl Iterator<Item = (usize, bool, (f32, f32))> + '_ {
+        paths.iter().enumerate().flat_map(|(index, path)| {
+            path.iter().flat_map(move |path| {
+                let backward = (!path.closed).then(|| (index, true, path.end()));
+                std::iter::once((index, false, path.start())).chain(backward)
+            })
+        })
+    }
+
+    ///The cell containing a point, or the nearest cell if it is outside the grid
+    fn cell(&self, (x, y): (f32, f32)) -> (usize, usize) {
+        let clamp = |v: f32, count: usize| (v.max(0.0) as usize).min(count - 1);
+        (
+            clamp((x - self.min.0) / self.cell_size, self.columns),
+            clamp((y - self.min.1) / self.cell_size, self.rows),
+        )
+    }
+
+    ///The nearest end to a point, as the index of its path and whether the path must be reversed.
+    ///There must be at least one path left.
+    fn nearest(&self, position: (f32, f32), paths: &[Option<Polyline>]) -> (usize, bool) {
+        let (column, row) = self.cell(position);
+        let mut best: Option<(f32, usize, bool)> = None;
+        for ring in 0..self.columns.max(self.rows) {
+            //Every end in a further ring is at least this far away
+            if best.is_some_and(|(d, _, _)| d <= (ring as f32 - 1.0) * self.cell_size) {
+                break;
+            }
+            for (x, y) in self.ring(column, row, ring) {
+                for &(index, reverse) in &self.cells[y * self.columns + x] {
+                    let path = paths[index].as_ref().unwrap();
+                    let end = if reverse { path.end() } else { path.start() };
+                    let candidate = (distance(position, end), index, reverse);
+                    if best.is_none_or(|best| candidate < best) {
+                        best = Some(candidate);
+                    }
+                }
+            }
+        }
+        let (_, index, reverse) = best.unwrap();
+        (index, reverse)
+    }
+
+    ///The cells of the grid whose rows and columns are both at most this far from a cell, and one of them exactly this far
+    fn ring(&self, column: usize, row: usize, ring: usize) -> impl Iterator<Item = (usize, usize)> {
+        let (columns, rows) = (self.columns as isize, self.rows as isize);
+        let (column, row, ring) = (column as isize, row as isize, ring as isize);
+        let edges = if ring == 0 {
+            vec![(column, row)]
+        } else {
+            let top_and_bottom = (column - ring..=column + ring).flat_map(|x| [(x, row - ring), (x, row + ring)]);
+            let sides = (row - ring + 1..row + ring).flat_map(|y| [(column - ring, y), (column + ring, y)]);
+            top_and_bottom.chain(sides).collect_vec()
+        };
+        edges
+            .into_iter()
+            .filter(move |(x, y)| (0..columns).contains(x) && (0..rows).contains(y))
+            .map(|(x, y)| (x as usize, y as usize))
+    }
+
+    fn remove(&mut self, index: usize, paths: &[Option<Polyline>]) {
+        let path = paths[index].as_ref().unwrap();
+        for point in [path.start(), path.end()] {
+            let (x, y) = self.cell(point);
+            self.cells[y * self.columns + x].retain(|(i, _)| *i != index);
+        }
+    }
+}
+
+impl Plot {
+    ///How far the pen moves while it is up, starting and finishing at the top left corner
+    pub fn travel_distance(&self) -> f32 {
+        let mut position = (0.0, 0.0);
+        let mut total = 0.0;
+        for path in self.layers.iter().flat_map(|l| l.paths.iter()) {
+            total += distance(position, path.start());
+            position = path.end();
+        }
+        total + distance(position, (0.0, 0.0))
+    }
+
+    ///Reorder the paths of each layer, and reverse open paths, so that each path starts close to where the last one ended.
+    ///Paths are chosen greedily, finding the nearest with a grid of their ends.
+    pub fn optimise_travel(&mut self) {
+        let mut position = (0.0, 0.0);
+        for layer in self.layers.iter_mut() {
+            let mut remaining = std::mem::take(&mut layer.paths).into_iter().map(Some).collect_vec();
+            let mut grid = EndpointGrid::new(&remaining);
+            for left in (1..=remaining.len()).rev() {
+                //Rebuild the grid once most of its paths are gone, so that searches do not cross many empty cells
+                if left * 4 < grid.paths {
+                    grid = EndpointGrid::new(&remaining);
+                }
+                let (index, reverse) = grid.nearest(position, &remaining);
+                grid.remove(index, &remaining);
+
+                let mut path = remaining[index].take().unwrap();
+                if reverse {
+                    path.points.reverse();
+                }
+                position = path.end();
+                layer.paths.push(path);
+            }
+        }
+    }
+
+    pub fn export(&self, format: PlotterFormat) -> String {
+        match format {
+            PlotterFormat::Hpgl => self.to_hpgl(),
+            PlotterFormat::GCode => self.to_gcode(),
+            PlotterFormat::Svg => self.to_svg(),
+        }
+    }
+
+    ///HPGL, with the origin at the bottom left and one pen per layer
+    pub fn to_hpgl(&self) -> String {
+        let to_units = |(x, y): (f32, f32)| {
+            (
+                (x * HPGL_UNITS_PER_MILLIMETRE).round() as i32,
+                ((self.page_height - y) * HPGL_UNITS_PER_MILLIMETRE).round() as i32,
+            )
+        };
+
+        let mut hpgl = "IN;\n".to_string();
+        for (i, layer) in self.layers.iter().enumerate() {
+            hpgl.push_str(&format!("SP{};\n", i + 1));
+            for path in layer.paths.iter() {
+                let (x, y) = to_units(path.start());
+                let points = path.pen_points().skip(1).map(|p| to_units(*p)).map(|(x, y)| format!("{x},{y}")).join(",");
+                hpgl.push_str(&format!("PU{x},{y};PD{points};\n"));
+            }
+        }
+        hpgl.push_str("PU;SP0;\n");
+        hpgl
+    }
+
+    ///G-code in millimetres, with the origin at the bottom left.
+    ///The pen is raised and lowered on the z axis, and the machine pauses for the pen to be changed between layers.
+    pub fn to_gcode(&self) -> String {
+        let to_machine = |(x, y): (f32, f32)| format!("X{:.3} Y{:.3}", x, self.page_height - y);
+
+        let mut gcode = "G21\nG90\nG0 Z5\n".to_string();
+        for (i, layer) in self.layers.iter().enumerate() {
+            if i > 0 {
+                gcode.push_str(&format!("M0 (Change to pen {})\n", i + 1));
+            }
+            for path in layer.paths.iter() {
+                gcode.push_str(&format!("G0 {}\nG1 Z0 F500\n", to_machine(path.start())));
+                for point in path.pen_points().skip(1) {
+                    gcode.push_str(&format!("G1 {} F1500\n", to_machine(*point)));
+                }
+                gcode.push_str("G0 Z5\n");
+            }
+        }
+        gcode.push_str("G0 X0 Y0\n");
+        gcode
+    }
+
+    ///Svg with one stroked path per line and an Inkscape layer per pen
+    pub fn to_svg(&self) -> String {
+        let mut svg = SvgWriter::new(String::new()).with_precision(Some(3));
+
+        //Writing to a string cannot fail
+        let _ = (|| -> std::fmt::Result {
+            svg.start_element(
+                "svg",
+                &[
+                    ("xmlns", &SVG_NAMESPACE),
+                    ("xmlns:inkscape", &INKSCAPE_NAMESPACE),
+                    ("width", &format!("{}mm", self.page_width)),
+                    ("height", &format!("{}mm", self.page_height)),
+                    ("viewBox", &format!("0 0 {} {}", self.page_width, self.page_height)),
+                ],
+            )?;
+            for (i, layer) in self.layers.iter().enumerate() {
+                let color = Rgba::from_hsla(layer.hue, 1.0, 0.4, 1.0).hex();
+                svg.start_element(
+                    "g",
+                    &[
+                        ("inkscape:groupmode", &"layer"),
+                        ("inkscape:label", &format!("{} pen", i + 1)),
+                        ("fill", &"none"),
+                        ("stroke", &color),
+                        ("stroke-width", &0.3),
+                        ("stroke-linecap", &"round"),
+                        ("stroke-linejoin", &"round"),
+                    ],
+                )?;
+                for path in layer.paths.iter() {
+                    let mut data = String::new();
+                    for (j, (x, y)) in path.points.iter().enumerate() {
+                        let command = if j == 0 { "M" } else { "L" };
+                        write!(data, "{command}{} {}", svg.number(*x), svg.number(*y))?;
+                    }
+                    if path.closed {
+                        data.push('Z');
+                    }
+                    svg.empty_element("path", &[("d", &data)])?;
+                }
+                svg.end_element()?;
+            }
+            svg.end_document()
+        })();
+
+        svg.into_inner()
+    }
+}
+
+///Lines across a closed outline, spacing apart and at an angle in radians.
+///Each line goes the opposite way to the last so the pen need not travel back across the shape.
+pub fn hatch(outline: &[(f32, f32)], spacing: f32, angle: f32) -> Vec<Polyline> {
+    let (sin, cos) = angle.sin_cos();
+    //Rotate the outline so the lines are horizontal
+    let rotated = outline.iter().map(|(x, y)| (x * cos + y * sin, y * cos - x * sin)).collect_vec();
+    let min_y = rotated.iter().map(|p| p.1).fold(f32::INFINITY, f32::min);
+    let max_y = rotated.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max);
+    if spacing.is_nan() || spacing <= 0.0 || !min_y.is_finite() || !max_y.is_finite() {
+        return vec![];
+    }
+
+    let mut lines = vec![];
+    let mut reverse = false;
+    //Lines are on a fixed grid so that neighbouring shapes' lines meet
+    let mut y = (min_y / spacing).ceil() * spacing;
+    while y < max_y {
+        let crossings = rotated
+            .iter()
+            .zip(rotated.iter().cycle().skip(1))
+            .filter(|(a, b)| (a.1 <= y) != (b.1 <= y))
+            .map(|(a, b)| a.0 + (y - a.1) / (b.1 - a.1) * (b.0 - a.0))
+            .sorted_by(|a, b| a.total_cmp(b))
+            .collect_vec();
+
+        let mut row = crossings
+            .into_iter()
+            .tuples()
+            .map(|(left, right)| {
+                let (start, end) = if reverse { (right, left) } else { (left, right) };
+                Polyline {
+                    points: [start, end].map(|x| (x * cos - y * sin, x * sin + y * cos)).to_vec(),
+                    closed: false,
+                }
+            })
+            .collect_vec();
+        if reverse {
+            row.reverse();
+        }
+        lines.extend(row);
+        reverse = !reverse;
+        y += spacing;
+    }
+    lines
+}
+
+///The parts of a polyline inside a rectangle
+pub fn clip_polyline(polyline: &Polyline, min: (f32, f32), max: (f32, f32)) -> Vec<Polyline> {
+    let inside = |p: (f32, f32)| p.0 >= min.0 && p.0 <= max.0 && p.1 >= min.1 && p.1 <= max.1;
+    if polyline.points.iter().all(|p| inside(*p)) {
+        return vec![polyline.clone()];
+    }
+
+    let mut parts = vec![];
+    let mut current: Vec<(f32, f32)> = vec![];
+    let points = polyline.pen_points().copied().collect_vec();
+    for (a, b) in points.iter().copied().tuple_windows() {
+        //Liang-Barsky clipping of the segment from a to b
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let (mut t0, mut t1) = (0.0f32, 1.0f32);
+        let mut visible = true;
+        for (p, q) in [(-dx, a.0 - min.0), (dx, max.0 - a.0), (-dy, a.1 - min.1), (dy, max.1 - a.1)] {
+            if p == 0.0 {
+                if q < 0.0 {
+                    visible = false;
+                }
+            } else if p < 0.0 {
+                t0 = t0.max(q / p);
+            } else {
+                t1 = t1.min(q / p);
+            }
+        }
+        if !visible || t0 > t1 {
+            if current.len() > 1 {
+                parts.push(std::mem::take(&mut current));
+            }
+            current.clear();
+            continue;
+        }
+
+        //Unclipped ends are kept exactly so that parts which meet can be joined
+        let start = if t0 > 0.0 { (a.0 + t0 * dx, a.1 + t0 * dy) } else { a };
+        let end = if t1 < 1.0 { (a.0 + t1 * dx, a.1 + t1 * dy) } else { b };
+        if t0 > 0.0 || current.is_empty() {
+            if current.len() > 1 {
+                parts.push(std::mem::take(&mut current));
+            }
+            current = vec![start];
+        }
+        current.push(end);
+        if t1 < 1.0 {
+            parts.push(std::mem::take(&mut current));
+        }
+    }
+    if current.len() > 1 {
+        parts.push(current);
+    }
+
+    //Join parts which meet, such as those either side of the start of a closed outline, so the pen is not lifted between them
+    let mut joined: Vec<Vec<(f32, f32)>> = vec![];
+    for part in parts {
+        match joined.last_mut() {
+            Some(last) if last.last() == part.first() => last.extend(part.into_iter().skip(1)),
+            _ => joined.push(part),
+        }
+    }
+    if polyline.closed && joined.len() > 1 && joined[joined.len() - 1].last() == joined[0].first() {
+        let first = joined.remove(0);
+        joined.last_mut().unwrap().extend(first.into_iter().skip(1));
+    }
+
+    joined
+        .into_iter()
+        .map(|points| Polyline { points, closed: false })
+        .collect_vec()
+}
+
+///Turns the outline of every shape into pen strokes.
+///The canvas is made as large as it can be inside the margins and centred on the paper.
+struct PlotRenderer {
+    settings: PlotterSettings,
+    ///How many millimetres there are to one unit of the canvas
+    scale: f32,
+    ///The centre of the canvas
+    centre: (f32, f32),
+    layers: Vec<PlotterLayer>,
+}
+
+impl PlotRenderer {
+    fn new(settings: &PlotterSettings) -> Self {
+        let layer_count = settings.hue_layers.max(1);
+        Self {
+            settings: *settings,
+            scale: 1.0,
+            centre: (0.0, 0.0),
+            layers: (0..layer_count)
+                .map(|i| PlotterLayer {
+                    hue: (i as f32 + 0.5) * 360.0 / layer_count as f32,
+                    paths: vec![],
+                })
+                .collect_vec(),
+        }
+    }
+
+    fn to_paper(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        (
+            (x - self.centre.0) * self.scale + self.settings.page_width / 2.0,
+            (y - self.centre.1) * self.scale + self.settings.page_height / 2.0,
+        )
+    }
+}
+
+impl Renderer for PlotRenderer {
+    type Output = Plot;
+
+    fn begin(&mut self, frame: &Frame) {
+        let settings = &self.settings;
+        let inner_width = (settings.page_width - 2.0 * settings.margin).max(0.0);
+        let inner_height = (settings.page_height - 2.0 * settings.margin).max(0.0);
+        self.scale = frame.bounds.scale_to_fit(inner_width, inner_height);
+        self.centre = frame.bounds.centre();
+    }
+
+    fn draw_primitive(&mut self, shape: &RenderShape) {
+        let settings = &self.settings;
+        let properties = &shape.node.absolute_properties;
+        if properties.a.value() <= 0.0 {
+            return;
+        }
+
+        let outline = shape
+            .primitive
+            .outline(properties, settings.tolerance / self.scale)
+            .into_iter()
+            .map(|p| self.to_paper(p))
+            .collect_vec();
+        if outline.iter().any(|(x, y)| !x.is_finite() || !y.is_finite()) {
+            return;
+        }
+
+        let min = (settings.margin, settings.margin);
+        let max = (settings.page_width - settings.margin, settings.page_height - settings.margin);
+        let layer_count = self.layers.len();
+        let hue = properties.h.value().rem_euclid(360.0);
+        let layer = &mut self.layers[((hue / 360.0 * layer_count as f32) as usize).min(layer_count - 1)];
+
+        if let Some(spacing) = settings.hatch_spacing {
+            //Darker shapes have more ink
+            let darkness = 1.0 - properties.v.value().clamp(0.0, 1.0);
+            if darkness > 0.05 {
+                for line in hatch(&outline, spacing / darkness, std::f32::consts::FRAC_PI_4) {
+                    layer.paths.extend(clip_polyline(&line, min, max));
+                }
+            }
+        }
+        layer.paths.extend(clip_polyline(
+            &Polyline {
+                points: outline,
+                closed: true,
+            },
+            min,
+            max,
+        ));
+    }
+
+    fn finish(mut self) -> Self::Output {
+        self.layers.retain(|l| !l.paths.is_empty());
+        let mut plot = Plot {
+            page_width: self.settings.page_width,
+            page_height: self.settings.page_height,
+            layers: self.layers,
+        };
+        if self.settings.optimise_travel {
+            plot.optimise_travel();
+        }
+        plot
+    }
+}
+
+impl NodeTree {
+    ///Turn the outline of every shape into pen strokes.
+    ///The canvas is made as large as it can be inside the margins and centred on the paper.
+    pub fn to_plot(&self, grammar: &CompiledGrammar, settings: &PlotterSettings) -> Plot {
+        self.render(grammar, PlotRenderer::new(settings))
+    }
+}
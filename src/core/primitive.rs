@@ -19,7 +19,7 @@ pub enum Primitive {
 }
 
 impl Primitive {
-    fn get_polygon_points(sides: usize) -> impl Iterator<Item = (f32, f32)> {
+    pub(crate) fn get_polygon_points(sides: usize) -> impl Iterator<Item = (f32, f32)> {
         (0..sides).map(move |side| {
             let degrees: f32 = (360.0f32 * side as f32 / sides as f32);
             let radians = degrees.to_radians();
@@ -32,98 +32,80 @@ impl Primitive {
         })
     }
 
-    pub fn to_svg(
-        &self,
-        relative_properties: &NodeProperties,
-        absolute_properties: &NodeProperties, 
-        rng: &mut StdRng,
-        
-    ) -> String {
-        let rotate_transform = if relative_properties.r == 0.0.into() {
-            "".to_string()
-        } else {
-            format!(
-                "style=\"transform: rotate({r}deg);\"",
-                r = relative_properties.r.random_value(rng)
-            )
-        };
-        let color = format!(
-            "fill=\"hsl({h}, {s}%, {l}%, {a}%)\" stroke=\"none\"",
-            h = absolute_properties.h.random_value(rng),
-            s = absolute_properties.s.random_value(rng) * 100.0,
-            l = absolute_properties.v.random_value(rng) * 100.0,
-            a = absolute_properties.a.random_value(rng) * 100.0,
-        );
-
-        match self {
-            Primitive::Circle => format!(
-                "<ellipse cx={x} cy={y} rx={rx} ry={ry} {color} {rotate_transform} />",
-                x = relative_properties.x.random_value(rng),
-                y = relative_properties.y.random_value(rng),
-                //ignore rotation
-                rx = relative_properties.p.random_value(rng) * absolute_properties.w.random_value(rng),
-                ry = relative_properties.p.random_value(rng) * absolute_properties.l.random_value(rng),
-                color = color,
-                rotate_transform = rotate_transform
-            ),
-            Primitive::Square => {
-                let x = relative_properties.x - (relative_properties.p * absolute_properties.w);
-                let y = relative_properties.y - (relative_properties.p * absolute_properties.l);
-
-                let width = relative_properties.p * absolute_properties.w * 2.0.into();
-                let height = relative_properties.p * absolute_properties.l * 2.0.into();
+    ///The outline of this shape, drawn with these absolute properties, as a polygon in absolute coordinates.
+    ///Curves are replaced by straight lines which are never further than the tolerance from them.
+    pub fn outline(&self, properties: &NodeProperties, tolerance: f32) -> Vec<(f32, f32)> {
+        let p = properties.p.value();
+        let half_width = p * properties.w.value();
+        let half_height = p * properties.l.value();
 
-                let rx = relative_properties.p * absolute_properties.c;
-                let ry = relative_properties.p * absolute_properties.c;
+        //How many lines to use for an arc of this radius and angle
+        let segments = |radius: f32, radians: f32| {
+            let step = if radius > tolerance {
+                2.0 * (1.0 - tolerance / radius).acos()
+            } else {
+                std::f32::consts::FRAC_PI_2
+            };
+            ((radians / step).ceil() as usize).clamp(1, 256)
+        };
+        //Move the ends of the lines outside the curve so that they cover the same area
+        let outset = |count: usize, radians: f32| {
+            let step = radians / count as f32;
+            (step / step.sin()).sqrt()
+        };
+        //A quarter of an ellipse, whose radii along x and y may differ
+        let arc = |centre: (f32, f32), (radius_x, radius_y): (f32, f32), start: f32, points: &mut Vec<(f32, f32)>| {
+            let count = segments(radius_x.max(radius_y), std::f32::consts::FRAC_PI_2);
+            let scale = outset(count, std::f32::consts::FRAC_PI_2);
+            points.extend((0..=count).map(|i| {
+                let angle = start + std::f32::consts::FRAC_PI_2 * i as f32 / count as f32;
+                (centre.0 + scale * radius_x * angle.cos(), centre.1 + scale * radius_y * angle.sin())
+            }));
+        };
 
-                format!("<rect x={x} y={y} width={width} height={height} rx={rx} ry={ry} {color}  {rotate_transform} />", 
-                x=x.random_value(rng),
-                y=y.random_value(rng),
-                rx = rx.random_value(rng),
-                ry=ry.random_value(rng),
-                width=width.random_value(rng),
-                height=height.random_value(rng),
-                color= color,
-                rotate_transform = rotate_transform
-            )
-            }
-            Primitive::RightTriangle => {
-                let points = [(0.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
-                    .into_iter()
-                    .flat_map(|(x, y)| {
-                        [
-                            (x * relative_properties.p.random_value(rng) * absolute_properties.w.random_value(rng))
-                                + relative_properties.x.random_value(rng),
-                            (y * relative_properties.p.random_value(rng) * absolute_properties.l.random_value(rng))
-                                + relative_properties.y.random_value(rng),
-                        ]
+        let unit_points = match self {
+            Primitive::Circle => {
+                let count = segments(half_width.abs().max(half_height.abs()), std::f32::consts::TAU).max(8);
+                let scale = outset(count, std::f32::consts::TAU);
+                (0..count)
+                    .map(|i| {
+                        let angle = std::f32::consts::TAU * i as f32 / count as f32;
+                        (scale * half_width * angle.cos(), scale * half_height * angle.sin())
                     })
-                    .join(" ");
-
-                format!(
-                    "<polygon points=\"{points}\" {color} {rotate_transform}/>",
-                    rotate_transform = rotate_transform
-                )
+                    .collect_vec()
             }
-
-            Primitive::Polygon(sides) => {
-                let points = Self::get_polygon_points(*sides)
-                    .flat_map(|(x, y)| {
-                        [
-                            (x * relative_properties.p.random_value(rng) * absolute_properties.w.random_value(rng))
-                                + relative_properties.x.random_value(rng),
-                            (y * relative_properties.p.random_value(rng) * absolute_properties.l.random_value(rng))
-                                + relative_properties.y.random_value(rng),
-                        ]
-                    })
-                    .join(" ");
-
-                format!(
-                    "<polygon points=\"{points}\" {color} {rotate_transform}/>",
-                    rotate_transform = rotate_transform
-                )
+            Primitive::Square => {
+                let (w, h) = (half_width.abs(), half_height.abs());
+                //A corner wider than the square is tall is elliptical, as it is in svg
+                let corner = p * properties.c.value();
+                let (cx, cy) = (corner.min(w), corner.min(h));
+                if corner > 0.0 {
+                    let mut points = vec![];
+                    arc((w - cx, h - cy), (cx, cy), 0.0, &mut points);
+                    arc((cx - w, h - cy), (cx, cy), std::f32::consts::FRAC_PI_2, &mut points);
+                    arc((cx - w, cy - h), (cx, cy), std::f32::consts::PI, &mut points);
+                    arc((w - cx, cy - h), (cx, cy), 3.0 * std::f32::consts::FRAC_PI_2, &mut points);
+                    points
+                } else {
+                    vec![(w, h), (-w, h), (-w, -h), (w, -h)]
+                }
             }
-        }
+            Primitive::RightTriangle => [(0.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+                .into_iter()
+                .map(|(x, y)| (x * half_width, y * half_height))
+                .collect_vec(),
+            Primitive::Polygon(sides) => Self::get_polygon_points(*sides)
+                .map(|(x, y)| (x * half_width, y * half_height))
+                .collect_vec(),
+        };
+
+        //Shapes rotate about their own centre
+        let (sin, cos) = properties.r.value().to_radians().sin_cos();
+        let (centre_x, centre_y) = (properties.x.value(), properties.y.value());
+        unit_points
+            .into_iter()
+            .map(|(x, y)| (centre_x + x * cos - y * sin, centre_y + x * sin + y * cos))
+            .collect_vec()
     }
 }
 
@@ -0,0 +1,65 @@
+use crate::core::prelude::*;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+///Counters for the nodes of one rule
+#[derive(Default, PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct RuleStatistics {
+    pub name: String,
+    ///The number of nodes of this rule which were expanded
+    pub invocations: usize,
+    ///How many times each case was chosen
+    pub cases_chosen: Vec<usize>,
+    ///The number of children of this rule's nodes which were kept
+    pub new_nodes: usize,
+    ///The children of this rule's nodes which were culled, by reason
+    pub culled: CullCounts,
+    ///The greatest depth of any expanded node of this rule
+    pub max_depth: usize,
+}
+
+///Where the nodes of an expansion came from, by rule
+#[derive(Default, PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub struct ExpansionProfile {
+    ///Indexed by rule id
+    pub rules: Vec<RuleStatistics>,
+}
+
+impl ExpansionProfile {
+    pub fn new(grammar: &CompiledGrammar) -> Self {
+        Self {
+            rules: grammar
+                .rules
+                .iter()
+                .map(|r| RuleStatistics {
+                    name: r.name.clone(),
+                    cases_chosen: vec![0; r.cases.len()],
+                    ..Default::default()
+                })
+                .collect_vec(),
+        }
+    }
+
+    ///Record the expansion of a node
+    pub fn record(&mut self, grammar: &CompiledGrammar, node: &Node, stats: &ExpandStatistics) {
+        if let CompiledMethod::Rule(rule) = grammar.get_invocation(node.invocation).method {
+            let rule = &mut self.rules[rule.0];
+            rule.invocations += 1;
+            if let Some(case) = stats.case {
+                rule.cases_chosen[case] += 1;
+            }
+            rule.new_nodes += stats.new_nodes;
+            rule.culled = rule.culled + &stats.culled;
+            rule.max_depth = rule.max_depth.max(node.absolute_properties.d);
+        }
+    }
+
+    ///The rules which made any nodes, those which made the most first
+    pub fn busiest(&self) -> Vec<&RuleStatistics> {
+        self.rules
+            .iter()
+            .filter(|r| r.invocations > 0)
+            .sorted_by_key(|r| std::cmp::Reverse(r.new_nodes + r.culled.total()))
+            .collect_vec()
+    }
+}
@@ -52,7 +52,7 @@ impl PropertyType {
     }
 }
 
-#[derive(PartialEq, PartialOrd, Clone, Copy, Serialize, Deserialize)]
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum PropertyKey {
     P,
     L,
@@ -61,6 +61,7 @@ pub enum PropertyKey {
 
     X,
     Y,
+    Z,
     R,
 
     H,
@@ -80,6 +81,7 @@ impl PropertyKey {
             PropertyKey::C => properties.c = value,
             PropertyKey::X => properties.x = value,
             PropertyKey::Y => properties.y = value,
+            PropertyKey::Z => properties.z = value,
             PropertyKey::R => properties.r = value,
             PropertyKey::H => properties.h = value,
             PropertyKey::S => properties.s = value,
@@ -88,6 +90,7 @@ impl PropertyKey {
             PropertyKey::D => properties.d = match value {
                 ValueOrRange::Value(v) => v.round() as usize,
                 ValueOrRange::Range { start, end } => start.round() as usize,
+                ValueOrRange::Compound(_) => value.min_value().round() as usize,
             }
         }
     }
@@ -100,6 +103,7 @@ impl PropertyKey {
             PropertyKey::C => properties.c,
             PropertyKey::X => properties.x,
             PropertyKey::Y => properties.y,
+            PropertyKey::Z => properties.z,
             PropertyKey::R => properties.r,
             PropertyKey::H => properties.h,
             PropertyKey::S => properties.s,
@@ -117,6 +121,7 @@ impl PropertyKey {
             PropertyKey::C => PropertyType::UnitInterval,
             PropertyKey::X => PropertyType::Any,
             PropertyKey::Y => PropertyType::Any,
+            PropertyKey::Z => PropertyType::Any,
             PropertyKey::R => PropertyType::Degrees,
             PropertyKey::H => PropertyType::Degrees,
             PropertyKey::S => PropertyType::UnitInterval,
@@ -138,6 +143,7 @@ impl std::str::FromStr for PropertyKey {
             "c" => Ok(PropertyKey::C),
             "x" => Ok(PropertyKey::X),
             "y" => Ok(PropertyKey::Y),
+            "z" => Ok(PropertyKey::Z),
             "r" => Ok(PropertyKey::R),
             "h" => Ok(PropertyKey::H),
             "s" => Ok(PropertyKey::S),
@@ -0,0 +1,301 @@
+use crate::core::prelude::*;
+
+///An image with 8 bit red, green, blue and alpha channels, which are not premultiplied
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct RgbaImage {
+    pub width: u32,
+    pub height: u32,
+    ///Four bytes per pixel, row by row from the top left
+    pub pixels: Vec<u8>,
+}
+
+impl RgbaImage {
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        let i = (y * self.width + x) as usize * 4;
+        [self.pixels[i], self.pixels[i + 1], self.pixels[i + 2], self.pixels[i + 3]]
+    }
+
+    pub fn write_png<W: std::io::Write>(&self, writer: W) -> Result<(), String> {
+        self.write_png_with_text(writer, &[])
+    }
+
+    ///Write this image as a png, with a utf-8 text chunk for each keyword and text
+    pub fn write_png_with_text<W: std::io::Write>(
+        &self,
+        writer: W,
+        text: &[(&str, &str)],
+    ) -> Result<(), String> {
+        let mut encoder = png::Encoder::new(writer, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        for (keyword, text) in text {
+            encoder
+                .add_itxt_chunk(keyword.to_string(), text.to_string())
+                .map_err(|e| e.to_string())?;
+        }
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer.write_image_data(&self.pixels).map_err(|e| e.to_string())
+    }
+
+    pub fn to_png(&self) -> Result<Vec<u8>, String> {
+        let mut png = vec![];
+        self.write_png(&mut png)?;
+        Ok(png)
+    }
+}
+
+///The largest distance, in pixels, between a curve and the lines drawn in its place
+const TOLERANCE: f32 = 0.1;
+
+///Draws anti aliased polygons, blending them in order
+pub struct Rasterizer {
+    width: usize,
+    height: usize,
+    ///The area of the plane drawn in the image
+    canvas: Bounds,
+    ///Premultiplied red, green, blue and alpha between 0 and 1
+    pixels: Vec<[f32; 4]>,
+    ///The signed area each edge covers in each pixel of the polygon being drawn, which is reused between polygons
+    accumulation: Vec<f32>,
+}
+
+impl Rasterizer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width: width as usize,
+            height: height as usize,
+            canvas: Bounds::CANVAS,
+            pixels: vec![[0.0; 4]; width as usize * height as usize],
+            accumulation: vec![],
+        }
+    }
+
+    ///Draw this area of the plane, rather than the unit canvas
+    pub fn with_canvas(mut self, canvas: Bounds) -> Self {
+        self.canvas = canvas;
+        self
+    }
+
+    ///How many pixels there are to one unit of the canvas.
+    ///The canvas is fitted inside the image and centred, as svg does.
+    pub fn scale(&self) -> f32 {
+        self.canvas.scale_to_fit(self.width as f32, self.height as f32)
+    }
+
+    ///The position of this point of the canvas in pixels
+    pub fn to_pixels(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        let scale = self.scale();
+        let (centre_x, centre_y) = self.canvas.centre();
+        (
+            (x - centre_x) * scale + self.width as f32 / 2.0,
+            (y - centre_y) * scale + self.height as f32 / 2.0,
+        )
+    }
+
+    ///Fill a polygon, whose points are in pixels
+    pub fn fill_polygon(&mut self, points: &[(f32, f32)], color: Rgba) {
+        if color.a <= 0.0 {
+            return;
+        }
+        let points = clip_polygon(points, self.width as f32, self.height as f32);
+        if points.len() < 3 {
+            return;
+        }
+
+        let min_x = points.iter().map(|p| p.0).fold(f32::INFINITY, f32::min).floor();
+        let max_x = points.iter().map(|p| p.0).fold(f32::NEG_INFINITY, f32::max).ceil();
+        let min_y = points.iter().map(|p| p.1).fold(f32::INFINITY, f32::min).floor();
+        let max_y = points.iter().map(|p| p.1).fold(f32::NEG_INFINITY, f32::max).ceil();
+        //Edges can write one column past their right end
+        let width = (max_x - min_x) as usize + 2;
+        let height = (max_y - min_y) as usize;
+
+        self.accumulation.clear();
+        self.accumulation.resize(width * height, 0.0);
+        for (i, start) in points.iter().enumerate() {
+            let end = points[(i + 1) % points.len()];
+            self.draw_edge(
+                (start.0 - min_x, start.1 - min_y),
+                (end.0 - min_x, end.1 - min_y),
+                width,
+                height,
+            );
+        }
+
+        let (red, green, blue) = (
+            color.r as f32 / 255.0,
+            color.g as f32 / 255.0,
+            color.b as f32 / 255.0,
+        );
+        for row in 0..height {
+            let y = row + min_y as usize;
+            let mut area = 0.0;
+            for column in 0..width {
+                area += self.accumulation[row * width + column];
+                let coverage = f32::abs(area).min(1.0);
+                let x = column + min_x as usize;
+                if coverage > 0.0 && x < self.width && y < self.height {
+                    let alpha = color.a * coverage;
+                    let pixel = &mut self.pixels[y * self.width + x];
+                    let remaining = 1.0 - alpha;
+                    *pixel = [
+                        red * alpha + pixel[0] * remaining,
+                        green * alpha + pixel[1] * remaining,
+                        blue * alpha + pixel[2] * remaining,
+                        alpha + pixel[3] * remaining,
+                    ];
+                }
+            }
+        }
+    }
+
+    ///Add the signed area to the left of an edge to the accumulation buffer, so that the running sum along a row is the coverage.
+    ///The points must lie inside the buffer.
+    fn draw_edge(&mut self, start: (f32, f32), end: (f32, f32), width: usize, height: usize) {
+        if start.1 == end.1 {
+            return;
+        }
+        let (direction, top, bottom) = if start.1 < end.1 {
+            (1.0, start, end)
+        } else {
+            (-1.0, end, start)
+        };
+        let dx_dy = (bottom.0 - top.0) / (bottom.1 - top.1);
+        let mut x = top.0;
+
+        for row in (top.1 as usize)..height.min(bottom.1.ceil() as usize) {
+            let line = row * width;
+            let dy = ((row + 1) as f32).min(bottom.1) - (row as f32).max(top.1);
+            let next_x = x + dx_dy * dy;
+            let d = dy * direction;
+            let (x0, x1) = if x < next_x { (x, next_x) } else { (next_x, x) };
+            let x0_floor = x0.floor();
+            let x0_index = x0_floor as usize;
+            let x1_ceil = x1.ceil();
+            let x1_index = x1_ceil as usize;
+
+            if x1_index <= x0_index + 1 {
+                //The edge crosses one pixel of this row
+                let middle = 0.5 * (x + next_x) - x0_floor;
+                self.accumulation[line + x0_index] += d - d * middle;
+                self.accumulation[line + x0_index + 1] += d * middle;
+            } else {
+                let s = (x1 - x0).recip();
+                let x0_fraction = x0 - x0_floor;
+                let a0 = 0.5 * s * (1.0 - x0_fraction) * (1.0 - x0_fraction);
+                let x1_fraction = x1 - x1_ceil + 1.0;
+                let a_end = 0.5 * s * x1_fraction * x1_fraction;
+
+                self.accumulation[line + x0_index] += d * a0;
+                if x1_index == x0_index + 2 {
+                    self.accumulation[line + x0_index + 1] += d * (1.0 - a0 - a_end);
+                } else {
+                    let a1 = s * (1.5 - x0_fraction);
+                    self.accumulation[line + x0_index + 1] += d * (a1 - a0);
+                    for column in x0_index + 2..x1_index - 1 {
+                        self.accumulation[line + column] += d * s;
+                    }
+                    let a2 = a1 + (x1_index - x0_index - 3) as f32 * s;
+                    self.accumulation[line + x1_index - 1] += d * (1.0 - a2 - a_end);
+                }
+                self.accumulation[line + x1_index] += d * a_end;
+            }
+            x = next_x;
+        }
+    }
+
+    pub fn into_image(self) -> RgbaImage {
+        let to_byte = |v: f32| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+        let pixels = self
+            .pixels
+            .iter()
+            .flat_map(|[r, g, b, a]| {
+                if *a > 0.0 {
+                    [to_byte(r / a), to_byte(g / a), to_byte(b / a), to_byte(*a)]
+                } else {
+                    [0; 4]
+                }
+            })
+            .collect();
+
+        RgbaImage {
+            width: self.width as u32,
+            height: self.height as u32,
+            pixels,
+        }
+    }
+}
+
+///Clip a convex polygon to the rectangle from the origin to (width, height)
+fn clip_polygon(points: &[(f32, f32)], width: f32, height: f32) -> Vec<(f32, f32)> {
+    let mut points = points.to_vec();
+    //Each edge of the rectangle as the axis it is on, its position and whether points inside are below it
+    for (axis, edge, inside_below) in [(0, 0.0, false), (0, width, true), (1, 0.0, false), (1, height, true)] {
+        let coordinate = |p: &(f32, f32)| if axis == 0 { p.0 } else { p.1 };
+        let is_inside = |p: &(f32, f32)| (coordinate(p) <= edge) == inside_below || coordinate(p) == edge;
+
+        let mut clipped = Vec::with_capacity(points.len() + 2);
+        for (i, current) in points.iter().enumerate() {
+            let previous = points[(i + points.len() - 1) % points.len()];
+            if is_inside(current) != is_inside(&previous) {
+                let t = (edge - coordinate(&previous)) / (coordinate(current) - coordinate(&previous));
+                let crossing = (
+                    previous.0 + t * (current.0 - previous.0),
+                    previous.1 + t * (current.1 - previous.1),
+                );
+                //Avoid rounding putting the crossing just outside the edge
+                clipped.push(if axis == 0 { (edge, crossing.1) } else { (crossing.0, edge) });
+            }
+            if is_inside(current) {
+                clipped.push(*current);
+            }
+        }
+        points = clipped;
+        if points.is_empty() {
+            break;
+        }
+    }
+    points
+}
+
+///Fills the shapes of a tree, drawing the area of its frame
+impl Renderer for Rasterizer {
+    type Output = RgbaImage;
+
+    fn begin(&mut self, frame: &Frame) {
+        self.canvas = frame.bounds;
+        if let Some(background) = frame.background {
+            let Bounds { min_x, min_y, max_x, max_y } = frame.bounds;
+            let corners = [(min_x, min_y), (max_x, min_y), (max_x, max_y), (min_x, max_y)]
+                .map(|p| self.to_pixels(p));
+            self.fill_polygon(&corners, background);
+        }
+    }
+
+    fn draw_primitive(&mut self, shape: &RenderShape) {
+        let tolerance = TOLERANCE / self.scale();
+        let points = shape
+            .primitive
+            .outline(&shape.node.absolute_properties, tolerance)
+            .into_iter()
+            .map(|p| self.to_pixels(p))
+            .collect::<Vec<_>>();
+        self.fill_polygon(&points, shape.color);
+    }
+
+    fn finish(self) -> Self::Output {
+        self.into_image()
+    }
+}
+
+impl NodeTree {
+    ///Draw this tree to an image of the given size in pixels
+    pub fn to_image(&self, grammar: &CompiledGrammar, width: u32, height: u32) -> RgbaImage {
+        self.render(grammar, Rasterizer::new(width, height))
+    }
+
+    ///Draw this tree to a png of the given size in pixels
+    pub fn to_png(&self, grammar: &CompiledGrammar, width: u32, height: u32) -> Result<Vec<u8>, String> {
+        self.to_image(grammar, width, height).to_png()
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+
+use crate::core::prelude::*;
+use serde::{Deserialize, Serialize};
+
+///Where a shape is drawn.
+///The unit shape, whose centre is at the origin and whose half width and half height are 1,
+///is scaled by the half width and half height, rotated clockwise about its centre and then moved to its centre.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ShapeTransform {
+    pub x: f32,
+    pub y: f32,
+    ///The absolute z of the shape, which groups do not change
+    pub z: f32,
+    ///Clockwise rotation in degrees
+    pub rotation: f32,
+    pub half_width: f32,
+    pub half_height: f32,
+    ///The radius of rounded corners, which may be more than the half width or half height
+    pub corner: f32,
+}
+
+impl ShapeTransform {
+    ///Where a shape with these absolute properties is on the canvas
+    pub fn absolute(properties: &NodeProperties) -> Self {
+        Self::relative(&RelativeTransform::from_properties(properties), properties)
+    }
+
+    ///Where a shape is inside the group of its parent, from its transform relative to its parent and its absolute properties
+    pub fn relative(transform: &RelativeTransform, properties: &NodeProperties) -> Self {
+        Self {
+            x: transform.x,
+            y: transform.y,
+            z: properties.z.value(),
+            rotation: transform.r,
+            half_width: transform.p * properties.w.value(),
+            half_height: transform.p * properties.l.value(),
+            corner: transform.p * properties.c.value(),
+        }
+    }
+}
+
+///A shape for a renderer to draw
+#[derive(Clone, Copy, PartialEq)]
+pub struct RenderShape<'a> {
+    pub primitive: Primitive,
+    ///Where the shape is on the canvas
+    pub transform: ShapeTransform,
+    ///Where the shape is inside the innermost group containing it
+    pub group_transform: ShapeTransform,
+    pub color: Rgba,
+    ///The node which draws the shape, for renderers which need more of its properties
+    pub node: &'a Node,
+    ///The names of the rules which led to this shape, starting at the top level or at the reused group containing it
+    pub rule_path: &'a [&'a str],
+}
+
+///Draws the shapes of a tree, in order, inside nested groups.
+///Renderers which only need to know where shapes are on the canvas can ignore the groups.
+pub trait Renderer {
+    type Output;
+
+    ///Start drawing, before any group or shape
+    fn begin(&mut self, _frame: &Frame) {}
+
+    ///Start a group inside the current group, placed by this transform
+    fn begin_group(&mut self, _transform: &RelativeTransform) {}
+
+    ///End the most recently started group
+    fn end_group(&mut self) {}
+
+    fn draw_primitive(&mut self, shape: &RenderShape);
+
+    ///Finish drawing, after every group has ended
+    fn finish(self) -> Self::Output;
+}
+
+///A renderer which can define a group once and then draw it wherever it is used
+pub trait InstancingRenderer: Renderer {
+    ///Start the definitions, after drawing begins and before any group or shape is drawn
+    fn begin_definitions(&mut self) {}
+
+    ///End the definitions
+    fn end_definitions(&mut self) {}
+
+    ///Start defining a group, whose contents follow as they would be drawn
+    fn begin_definition(&mut self, id: usize);
+
+    ///End the most recently started definition
+    fn end_definition(&mut self);
+
+    ///Draw a group defined earlier, placed by this transform
+    fn use_definition(&mut self, id: usize, transform: &RelativeTransform);
+}
+
+///What a node draws, in its own coordinates
+#[derive(PartialEq, Eq, Hash, Clone)]
+enum InstanceContent<K> {
+    ///A node without children, by its key
+    Leaf(K),
+    ///The transforms and contents of the children of a group
+    Group(Vec<(Option<K>, usize)>),
+}
+
+///The distinct contents drawn by the nodes of a tree, so that each group drawn more than once need only be defined once
+pub struct RenderInstances {
+    ///The content drawn by each node
+    node_contents: Vec<usize>,
+    ///A node which draws each content
+    representatives: Vec<usize>,
+    ///Whether each content is a group which is drawn more than once
+    shared: Vec<bool>,
+}
+
+impl Node {
+    ///The shape this node draws, if it is a primitive
+    pub fn render_shape(&self, grammar: &CompiledGrammar) -> Option<RenderShape<'_>> {
+        match grammar.get_invocation(self.invocation).method {
+            CompiledMethod::Primitive(primitive) => Some(RenderShape {
+                primitive,
+                transform: ShapeTransform::absolute(&self.absolute_properties),
+                group_transform: ShapeTransform::relative(
+                    &self.transform,
+                    &self.absolute_properties,
+                ),
+                color: Rgba::from_properties(&self.absolute_properties),
+                node: self,
+                rule_path: &[],
+            }),
+            CompiledMethod::Rule(_) | CompiledMethod::Root => None,
+        }
+    }
+
+    ///Whether this node is drawn as a group of its children
+    pub(crate) fn is_group(&self) -> bool {
+        matches!(&self.children, Some(children) if !children.is_empty())
+    }
+
+    ///The name of the rule this node invokes, if it invokes one
+    pub fn rule_name<'g>(&self, grammar: &'g CompiledGrammar) -> Option<&'g str> {
+        match grammar.get_invocation(self.invocation).method {
+            CompiledMethod::Rule(rule) => Some(&grammar.rules[rule.0].name),
+            CompiledMethod::Primitive(_) | CompiledMethod::Root => None,
+        }
+    }
+
+    ///Draw this node as it is drawn when it has no children
+    pub(crate) fn render_leaf<R: Renderer>(
+        &self,
+        grammar: &CompiledGrammar,
+        rule_path: &[&str],
+        renderer: &mut R,
+    ) {
+        if let Some(shape) = self.render_shape(grammar) {
+            renderer.draw_primitive(&RenderShape { rule_path, ..shape });
+        }
+    }
+}
+
+impl NodeTree {
+    ///Draw every shape of this tree with a renderer
+    pub fn render<R: Renderer>(&self, grammar: &CompiledGrammar, mut renderer: R) -> R::Output {
+        renderer.begin(&self.frame(grammar));
+        self.render_node(Self::ROOT, grammar, &mut vec![], &mut renderer);
+        renderer.finish()
+    }
+
+    fn render_node<'g, R: Renderer>(
+        &self,
+        index: usize,
+        grammar: &'g CompiledGrammar,
+        rule_path: &mut Vec<&'g str>,
+        renderer: &mut R,
+    ) {
+        let node = &self.nodes[index];
+
+        match node.children.clone() {
+            Some(children) if !children.is_empty() => {
+                let rule = node.rule_name(grammar);
+                rule_path.extend(rule);
+                renderer.begin_group(&node.transform);
+                for child in children {
+                    self.render_node(child, grammar, rule_path, renderer);
+                }
+                renderer.end_group();
+                if rule.is_some() {
+                    rule_path.pop();
+                }
+            }
+            _ => node.render_leaf(grammar, rule_path, renderer),
+        }
+    }
+
+    ///Find the groups of this tree which draw the same thing.
+    ///Nodes without children draw the same thing if their keys are equal,
+    ///and groups do if their children draw the same things and the transform keys of the child groups are equal.
+    pub fn find_instances<K: Eq + std::hash::Hash + Clone>(
+        &self,
+        leaf_key: impl Fn(&Node) -> K,
+        transform_key: impl Fn(&RelativeTransform) -> Option<K>,
+    ) -> RenderInstances {
+        let mut contents = vec![];
+        let mut representatives = vec![];
+        let mut uses = vec![];
+        let mut ids: HashMap<InstanceContent<K>, usize> = Default::default();
+        let mut node_contents = vec![0; self.nodes.len()];
+
+        //Children always come after their parents so this visits every child before its parent
+        for index in (0..self.nodes.len()).rev() {
+            let node = &self.nodes[index];
+            let content = match node.children.clone() {
+                Some(children) if !children.is_empty() => InstanceContent::Group(
+                    children
+                        .map(|c| {
+                            let child = &self.nodes[c];
+                            let transform = if child.is_group() {
+                                transform_key(&child.transform)
+                            } else {
+                                None
+                            };
+                            (transform, node_contents[c])
+                        })
+                        .collect(),
+                ),
+                _ => InstanceContent::Leaf(leaf_key(node)),
+            };
+
+            node_contents[index] = match ids.get(&content) {
+                Some(id) => *id,
+                None => {
+                    //Each distinct group is drawn once, so it uses each of its children once
+                    if let InstanceContent::Group(children) = &content {
+                        for (_, child) in children {
+                            uses[*child] += 1;
+                        }
+                    }
+                    let id = contents.len();
+                    ids.insert(content.clone(), id);
+                    contents.push(content);
+                    representatives.push(index);
+                    uses.push(0);
+                    id
+                }
+            };
+        }
+
+        uses[node_contents[Self::ROOT]] += 1;
+        let shared = contents
+            .iter()
+            .zip(uses)
+            .map(|(content, uses)| matches!(content, InstanceContent::Group(_)) && uses > 1)
+            .collect();
+        RenderInstances {
+            node_contents,
+            representatives,
+            shared,
+        }
+    }
+
+    ///Draw every shape of this tree with a renderer, defining each shared group once and then using it
+    pub fn render_instanced<R: InstancingRenderer>(
+        &self,
+        grammar: &CompiledGrammar,
+        instances: &RenderInstances,
+        mut renderer: R,
+    ) -> R::Output {
+        renderer.begin(&self.frame(grammar));
+        //Contents are numbered children first, so no group is used before it is defined
+        let shared = (0..instances.shared.len())
+            .filter(|c| instances.shared[*c])
+            .collect::<Vec<_>>();
+        if !shared.is_empty() {
+            renderer.begin_definitions();
+            for content in shared {
+                renderer.begin_definition(content);
+                let index = instances.representatives[content];
+                self.render_instance_children(index, grammar, instances, &mut vec![], &mut renderer);
+                renderer.end_definition();
+            }
+            renderer.end_definitions();
+        }
+        self.render_instance(
+            Self::ROOT,
+            &RelativeTransform::IDENTITY,
+            grammar,
+            instances,
+            &mut vec![],
+            &mut renderer,
+        );
+        renderer.finish()
+    }
+
+    fn render_instance<'g, R: InstancingRenderer>(
+        &self,
+        index: usize,
+        transform: &RelativeTransform,
+        grammar: &'g CompiledGrammar,
+        instances: &RenderInstances,
+        rule_path: &mut Vec<&'g str>,
+        renderer: &mut R,
+    ) {
+        let node = &self.nodes[index];
+        let content = instances.node_contents[index];
+        match node.children.clone() {
+            Some(children) if !children.is_empty() => {
+                if instances.shared[content] {
+                    renderer.use_definition(content, transform);
+                } else {
+                    renderer.begin_group(transform);
+                    self.render_instance_children(index, grammar, instances, rule_path, renderer);
+                    renderer.end_group();
+                }
+            }
+            _ => node.render_leaf(grammar, rule_path, renderer),
+        }
+    }
+
+    fn render_instance_children<'g, R: InstancingRenderer>(
+        &self,
+        index: usize,
+        grammar: &'g CompiledGrammar,
+        instances: &RenderInstances,
+        rule_path: &mut Vec<&'g str>,
+        renderer: &mut R,
+    ) {
+        let node = &self.nodes[index];
+        let rule = node.rule_name(grammar);
+        rule_path.extend(rule);
+        for child in node.children.clone().unwrap_or_default() {
+            let transform = &self.nodes[child].transform;
+            self.render_instance(child, transform, grammar, instances, rule_path, renderer);
+        }
+        if rule.is_some() {
+            rule_path.pop();
+        }
+    }
+}
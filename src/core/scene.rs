@@ -0,0 +1,91 @@
+use crate::core::prelude::*;
+use serde::{Deserialize, Serialize};
+
+///The version of the scene format. It goes up whenever a field is removed or changes meaning.
+pub const SCENE_VERSION: u32 = 1;
+
+///Every shape of an expanded tree, in the order they are drawn
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub version: u32,
+    ///The area drawn and the colour behind it, which was added without changing the version
+    #[serde(default)]
+    pub frame: Frame,
+    pub leaves: Vec<SceneLeaf>,
+}
+
+///A shape in absolute coordinates, with y pointing down
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct SceneLeaf {
+    pub primitive: Primitive,
+    pub transform: ShapeTransform,
+    pub color: Rgba,
+    ///How many invocations deep the shape is
+    pub depth: usize,
+    ///The names of the rules which led to this shape, starting at the top level
+    pub rule_path: Vec<String>,
+}
+
+impl Scene {
+    pub fn to_json(&self) -> String {
+        //Every field is a plain value so this cannot fail
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    ///Read a scene, failing if it was written by a newer version of convext
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        #[derive(Deserialize)]
+        struct Versioned {
+            version: u32,
+        }
+
+        let Versioned { version } = serde_json::from_str(json).map_err(|e| e.to_string())?;
+        if version > SCENE_VERSION {
+            return Err(format!(
+                "Scene version {} is newer than {}",
+                version, SCENE_VERSION
+            ));
+        }
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+}
+
+impl Renderer for Scene {
+    type Output = Self;
+
+    fn begin(&mut self, frame: &Frame) {
+        self.frame = *frame;
+    }
+
+    fn draw_primitive(&mut self, shape: &RenderShape) {
+        self.leaves.push(SceneLeaf {
+            primitive: shape.primitive,
+            transform: shape.transform,
+            color: shape.color,
+            depth: shape.node.absolute_properties.d,
+            rule_path: shape.rule_path.iter().map(|name| name.to_string()).collect(),
+        });
+    }
+
+    fn finish(self) -> Self::Output {
+        self
+    }
+}
+
+impl NodeTree {
+    pub fn to_scene(&self, grammar: &CompiledGrammar) -> Scene {
+        self.render(
+            grammar,
+            Scene {
+                version: SCENE_VERSION,
+                frame: Default::default(),
+                leaves: vec![],
+            },
+        )
+    }
+
+    ///Write every shape of this tree as versioned json
+    pub fn to_json(&self, grammar: &CompiledGrammar) -> String {
+        self.to_scene(grammar).to_json()
+    }
+}
@@ -0,0 +1,153 @@
+use std::fmt::Write;
+
+use crate::core::prelude::*;
+use itertools::Itertools;
+use rand::prelude::StdRng;
+
+///Adapts an `std::io::Write` so it can be used as a `std::fmt::Write` sink
+pub struct IoWriter<W: std::io::Write> {
+    pub inner: W,
+    pub error: Option<std::io::Error>,
+}
+
+impl<W: std::io::Write> IoWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, error: None }
+    }
+}
+
+impl<W: std::io::Write> std::fmt::Write for IoWriter<W> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.inner.write_all(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            std::fmt::Error
+        })
+    }
+}
+
+///A node which has been entered but whose children have not all been written
+struct StreamFrame {
+    children: std::vec::IntoIter<Node>,
+    ///Whether the node invokes a rule, whose name is then the last of the rule path
+    is_rule: bool,
+}
+
+impl CompiledGrammar {
+    ///Expand this grammar depth first, writing the svg directly to the writer.
+    ///See `render_streaming` for when the output is the same as `expand` followed by `NodeTree::to_svg`.
+    pub fn write_svg<W: Write>(
+        &self,
+        settings: &ExpandSettings,
+        rng: &mut StdRng,
+        writer: &mut W,
+    ) -> std::fmt::Result {
+        let svg = SvgWriter::new(writer);
+        self.render_streaming(settings, rng, SvgRenderer::new(svg)).map(|_| ())
+    }
+
+    ///Expand this grammar depth first, drawing each node with the renderer as soon as it is made.
+    ///Only the nodes on the current path, and their unwritten siblings, are kept in memory.
+    ///The shapes and groups are the same as those of `expand` followed by `NodeTree::render` only when the node budget does not run out,
+    ///no time budget is set, occlusion culling is off and the grammar does not fit the canvas around its shapes.
+    ///When the node budget runs out, as it usually does for very large drawings, it is spent depth first rather than breadth first,
+    ///so as many nodes are made but they are not the same ones.
+    ///The time budget is ignored so that the output is reproducible, and occlusion culling is not done as it needs the whole tree.
+    ///For the same reason the canvas is never fitted around the shapes drawn, so the declared canvas is used instead.
+    pub fn render_streaming<R: Renderer>(
+        &self,
+        settings: &ExpandSettings,
+        rng: &mut StdRng,
+        renderer: R,
+    ) -> R::Output {
+        self.render_streaming_with_statistics(settings, rng, renderer).0
+    }
+
+    ///Like `render_streaming`, also returning the statistics of the expansion
+    pub fn render_streaming_with_statistics<R: Renderer>(
+        &self,
+        settings: &ExpandSettings,
+        rng: &mut StdRng,
+        mut renderer: R,
+    ) -> (R::Output, ExpandStatistics) {
+        let settings = &ExpandSettings {
+            max_milliseconds: None,
+            occlusion_culling: false,
+            ..*settings
+        };
+        let (root, top_level) = self.make_root(rng);
+        let mut budget = ExpandBudget::new(settings);
+        let mut statistics = ExpandStatistics::default();
+
+        renderer.begin(&self.frame);
+        let mut stack = vec![];
+        let mut rule_path = vec![];
+        if top_level.is_empty() {
+            root.render_leaf(self, &rule_path, &mut renderer);
+        } else {
+            renderer.begin_group(&root.transform);
+            stack.push(StreamFrame {
+                children: top_level.into_iter(),
+                is_rule: false,
+            });
+        }
+
+        while let Some(frame) = stack.last_mut() {
+            match frame.children.next() {
+                Some(node) => {
+                    //Once the budget has run out every remaining node is left unexpanded, as in `expand`
+                    let children = if budget.truncated.is_none() {
+                        let mut stats = ExpandStatistics::default();
+                        let children = node.get_children(settings, self, &mut stats);
+                        match budget.take(children, stats) {
+                            Some((children, stats)) => {
+                                statistics = statistics + &stats;
+                                children
+                            }
+                            None => vec![],
+                        }
+                    } else {
+                        vec![]
+                    };
+
+                    if children.is_empty() {
+                        node.render_leaf(self, &rule_path, &mut renderer);
+                    } else {
+                        let rule = node.rule_name(self);
+                        rule_path.extend(rule);
+                        renderer.begin_group(&node.transform);
+                        stack.push(StreamFrame {
+                            children: children.into_iter(),
+                            is_rule: rule.is_some(),
+                        });
+                    }
+                }
+                None => {
+                    renderer.end_group();
+                    if frame.is_rule {
+                        rule_path.pop();
+                    }
+                    stack.pop();
+                }
+            }
+        }
+
+        statistics.truncated = budget.truncated;
+        (renderer.finish(), statistics)
+    }
+
+    ///Expand this grammar depth first, writing the svg directly to an `std::io::Write`
+    pub fn write_svg_io<W: std::io::Write>(
+        &self,
+        settings: &ExpandSettings,
+        rng: &mut StdRng,
+        writer: W,
+    ) -> std::io::Result<()> {
+        let mut io_writer = IoWriter::new(writer);
+        match self.write_svg(settings, rng, &mut io_writer) {
+            Ok(()) => Ok(()),
+            Err(_) => Err(io_writer
+                .error
+                .unwrap_or_else(|| std::io::Error::other("format error"))),
+        }
+    }
+}
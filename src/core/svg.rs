@@ -0,0 +1,510 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
+
+use crate::core::prelude::*;
+use serde::{Deserialize, Serialize};
+
+pub const SVG_NAMESPACE: &str = "http://www.w3.org/2000/svg";
+pub const XLINK_NAMESPACE: &str = "http://www.w3.org/1999/xlink";
+
+///Options which make the svg smaller without visibly changing what it draws
+#[derive(Clone, Copy, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct SvgSettings {
+    ///The number of decimal places to round numbers to, if any
+    pub precision: Option<usize>,
+    ///Write fills used by many shapes once, as css classes
+    pub css_classes: bool,
+    ///Write groups which are drawn more than once in `<defs>` and draw them with `<use>`
+    pub instancing: bool,
+}
+
+impl SvgSettings {
+    pub const COMPACT: SvgSettings = SvgSettings {
+        precision: Some(4),
+        css_classes: true,
+        instancing: true,
+    };
+}
+
+///A number written with at most some number of decimal places, and no trailing zeros
+pub struct SvgNumber(pub f32, pub Option<usize>);
+
+impl Display for SvgNumber {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.1 {
+            None => write!(f, "{}", self.0),
+            Some(precision) => {
+                let text = format!("{:.*}", precision, self.0);
+                let text = if text.contains('.') {
+                    text.trim_end_matches('0').trim_end_matches('.')
+                } else {
+                    text.as_str()
+                };
+                match text {
+                    "-0" => f.write_str("0"),
+                    _ => f.write_str(text),
+                }
+            }
+        }
+    }
+}
+
+///Writes well formed svg, one element per line.
+///Attribute values are quoted and escaped.
+pub struct SvgWriter<W: Write> {
+    writer: W,
+    open_elements: Vec<&'static str>,
+    precision: Option<usize>,
+    ///Each fill style which has been counted and how many shapes use it, in the order they were first counted
+    fill_styles: Vec<(String, usize)>,
+    fill_style_ids: HashMap<String, usize>,
+    ///The css class of each fill style used by enough shapes
+    classes: HashMap<String, usize>,
+}
+
+impl<W: Write> SvgWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            open_elements: vec![],
+            precision: None,
+            fill_styles: vec![],
+            fill_style_ids: Default::default(),
+            classes: Default::default(),
+        }
+    }
+
+    ///Round numbers to this many decimal places
+    pub fn with_precision(mut self, precision: Option<usize>) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    pub fn number(&self, value: f32) -> SvgNumber {
+        SvgNumber(value, self.precision)
+    }
+
+    ///Count a shape of this colour, so that colours used by many shapes can be filled using a css class.
+    ///Shapes must be counted before the document is started.
+    pub fn add_class(&mut self, color: &Rgba) {
+        let style = self.fill_style(color);
+        match self.fill_style_ids.get(&style) {
+            Some(id) => self.fill_styles[*id].1 += 1,
+            None => {
+                self.fill_style_ids.insert(style.clone(), self.fill_styles.len());
+                self.fill_styles.push((style, 1));
+            }
+        }
+    }
+
+    ///The css class for shapes of this colour, if it has one
+    pub fn class_name(&self, color: &Rgba) -> Option<String> {
+        if self.classes.is_empty() {
+            return None;
+        }
+        self.classes.get(&self.fill_style(color)).map(|id| format!("c{id}"))
+    }
+
+    fn fill_style(&self, color: &Rgba) -> String {
+        if color.a < 1.0 {
+            format!("fill:{};fill-opacity:{}", color.hex(), self.number(color.a))
+        } else {
+            format!("fill:{}", color.hex())
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    ///Start the root element, showing the area of the frame
+    pub fn start_document(&mut self, frame: &Frame) -> std::fmt::Result {
+        let bounds = frame.bounds;
+        let view_box = format!(
+            "{} {} {} {}",
+            self.number(bounds.min_x),
+            self.number(bounds.min_y),
+            self.number(bounds.width()),
+            self.number(bounds.height())
+        );
+        self.start_element(
+            "svg",
+            &[
+                ("xmlns", &SVG_NAMESPACE),
+                ("xmlns:xlink", &XLINK_NAMESPACE),
+                ("viewBox", &view_box),
+                ("width", &"100%"),
+                ("height", &"100%"),
+            ],
+        )?;
+
+        let shared = std::mem::take(&mut self.fill_styles)
+            .into_iter()
+            //A class is only worth writing if it makes the shapes using it shorter by more than its own length
+            .filter(|(style, uses)| *uses * style.len().saturating_sub(8) > style.len() + 6)
+            .map(|(style, _)| style)
+            .collect::<Vec<_>>();
+        if !shared.is_empty() {
+            self.start_element("style", &[])?;
+            for (id, style) in shared.iter().enumerate() {
+                write!(Escaper(&mut self.writer), ".c{id}{{{style}}}")?;
+                self.writer.write_char('\n')?;
+            }
+            self.end_element()?;
+        }
+        self.fill_style_ids.clear();
+        self.classes = shared.into_iter().enumerate().map(|(id, style)| (style, id)).collect();
+
+        if let Some(color) = frame.background {
+            let [x, y, width, height] =
+                [bounds.min_x, bounds.min_y, bounds.width(), bounds.height()].map(|v| self.number(v));
+            let fill = color.hex();
+            let opacity = self.number(color.a);
+            let mut attributes: Vec<(&str, &dyn Display)> = vec![
+                ("x", &x),
+                ("y", &y),
+                ("width", &width),
+                ("height", &height),
+                ("fill", &fill),
+            ];
+            if color.a < 1.0 {
+                attributes.push(("fill-opacity", &opacity));
+            }
+            self.empty_element("rect", &attributes)?;
+        }
+        Ok(())
+    }
+
+    ///Close every open element, including the root
+    pub fn end_document(&mut self) -> std::fmt::Result {
+        while !self.open_elements.is_empty() {
+            self.end_element()?;
+        }
+        Ok(())
+    }
+
+    pub fn start_element(&mut self, name: &'static str, attributes: &[(&str, &dyn Display)]) -> std::fmt::Result {
+        self.write_tag(name, attributes)?;
+        self.writer.write_str(">\n")?;
+        self.open_elements.push(name);
+        Ok(())
+    }
+
+    pub fn empty_element(&mut self, name: &str, attributes: &[(&str, &dyn Display)]) -> std::fmt::Result {
+        self.write_tag(name, attributes)?;
+        self.writer.write_str("/>\n")
+    }
+
+    ///Write an element containing only text
+    pub fn text_element(&mut self, name: &str, attributes: &[(&str, &dyn Display)], text: &str) -> std::fmt::Result {
+        self.write_tag(name, attributes)?;
+        self.writer.write_char('>')?;
+        Escaper(&mut self.writer).write_str(text)?;
+        writeln!(self.writer, "</{name}>")
+    }
+
+    ///Close the most recently started element
+    pub fn end_element(&mut self) -> std::fmt::Result {
+        match self.open_elements.pop() {
+            Some(name) => writeln!(self.writer, "</{name}>"),
+            None => Err(std::fmt::Error),
+        }
+    }
+
+    fn write_tag(&mut self, name: &str, attributes: &[(&str, &dyn Display)]) -> std::fmt::Result {
+        write!(self.writer, "<{name}")?;
+        for (key, value) in attributes {
+            write!(self.writer, " {key}=\"")?;
+            write!(Escaper(&mut self.writer), "{value}")?;
+            self.writer.write_char('"')?;
+        }
+        Ok(())
+    }
+}
+
+///Escapes everything written through it so it can go inside a double quoted attribute or an element
+struct Escaper<'a, W: Write>(&'a mut W);
+
+impl<'a, W: Write> Write for Escaper<'a, W> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        let mut rest = s;
+        while let Some(index) = rest.find(['&', '<', '>', '"', '\''].as_ref()) {
+            self.0.write_str(&rest[..index])?;
+            self.0.write_str(match rest.as_bytes()[index] {
+                b'&' => "&amp;",
+                b'<' => "&lt;",
+                b'>' => "&gt;",
+                b'"' => "&quot;",
+                _ => "&apos;",
+            })?;
+            rest = &rest[index + 1..];
+        }
+        self.0.write_str(rest)
+    }
+}
+
+impl<W: Write> SvgWriter<W> {
+    ///Write a shape as an svg element, placed inside its group
+    pub fn write_shape(&mut self, shape: &RenderShape) -> std::fmt::Result {
+        let transform = &shape.group_transform;
+        let centre_x = transform.x;
+        let centre_y = transform.y;
+        let half_width = transform.half_width;
+        let half_height = transform.half_height;
+
+        let color = shape.color;
+        let class = self.class_name(&color);
+        let fill = color.hex();
+        let opacity = self.number(color.a);
+        //Rotate about the centre of the shape, as groups do
+        let rotate = if centre_x == 0.0 && centre_y == 0.0 {
+            format!("rotate({})", self.number(transform.rotation))
+        } else {
+            format!(
+                "rotate({} {} {})",
+                self.number(transform.rotation),
+                self.number(centre_x),
+                self.number(centre_y)
+            )
+        };
+
+        let mut paint: Vec<(&str, &dyn Display)> = vec![];
+        match &class {
+            Some(class) => paint.push(("class", class)),
+            None => {
+                paint.push(("fill", &fill));
+                if color.a < 1.0 {
+                    paint.push(("fill-opacity", &opacity));
+                }
+            }
+        }
+        if transform.rotation != 0.0 {
+            paint.push(("transform", &rotate));
+        }
+
+        match shape.primitive {
+            Primitive::Circle => self.empty_element(
+                "ellipse",
+                &[
+                    [
+                        ("cx", &self.number(centre_x) as &dyn Display),
+                        ("cy", &self.number(centre_y)),
+                        ("rx", &self.number(half_width)),
+                        ("ry", &self.number(half_height)),
+                    ]
+                    .as_slice(),
+                    &paint,
+                ]
+                .concat(),
+            ),
+            Primitive::Square => {
+                let corner = transform.corner;
+                let corners = [("rx", &self.number(corner) as &dyn Display), ("ry", &self.number(corner))];
+                self.empty_element(
+                    "rect",
+                    &[
+                        [
+                            ("x", &self.number(centre_x - half_width) as &dyn Display),
+                            ("y", &self.number(centre_y - half_height)),
+                            ("width", &self.number(half_width * 2.0)),
+                            ("height", &self.number(half_height * 2.0)),
+                        ]
+                        .as_slice(),
+                        //Corners are square by default
+                        if corner == 0.0 { &[] } else { &corners },
+                        &paint,
+                    ]
+                    .concat(),
+                )
+            }
+            Primitive::RightTriangle | Primitive::Polygon(_) => {
+                let points = match shape.primitive {
+                    Primitive::Polygon(sides) => to_points(
+                        Primitive::get_polygon_points(sides),
+                        centre_x,
+                        centre_y,
+                        half_width,
+                        half_height,
+                        self.precision,
+                    ),
+                    _ => to_points(
+                        [(0.0, -1.0), (1.0, 1.0), (-1.0, 1.0)].into_iter(),
+                        centre_x,
+                        centre_y,
+                        half_width,
+                        half_height,
+                        self.precision,
+                    ),
+                };
+                self.empty_element("polygon", &[[("points", &points as &dyn Display)].as_slice(), &paint].concat())
+            }
+        }
+    }
+}
+
+fn to_points(
+    unit_points: impl Iterator<Item = (f32, f32)>,
+    centre_x: f32,
+    centre_y: f32,
+    half_width: f32,
+    half_height: f32,
+    precision: Option<usize>,
+) -> String {
+    let mut points = String::new();
+    for (i, (x, y)) in unit_points.enumerate() {
+        if i > 0 {
+            points.push(' ');
+        }
+        //Writing to a string cannot fail
+        let _ = write!(
+            points,
+            "{},{}",
+            SvgNumber(x * half_width + centre_x, precision),
+            SvgNumber(y * half_height + centre_y, precision)
+        );
+    }
+    points
+}
+
+impl RelativeTransform {
+    ///The svg transform placing a node inside its parent's group, if it is not the identity
+    pub(crate) fn svg_transform(&self, precision: Option<usize>) -> Option<String> {
+        let number = |v| SvgNumber(v, precision);
+        let mut transform = vec![];
+        if self.x != 0.0 || self.y != 0.0 {
+            transform.push(format!("translate({} {})", number(self.x), number(self.y)));
+        }
+        if self.p != 1.0 {
+            transform.push(format!("scale({})", number(self.p)));
+        }
+        if self.r != 0.0 {
+            transform.push(format!("rotate({})", number(self.r)));
+        }
+        if transform.is_empty() {
+            None
+        } else {
+            Some(transform.join(" "))
+        }
+    }
+}
+
+///Draws shapes as svg elements, with a `<g>` element for each group.
+///The document is started when drawing begins and ended when it finishes.
+pub struct SvgRenderer<W: Write> {
+    svg: SvgWriter<W>,
+    ///Whether every write so far has succeeded
+    result: std::fmt::Result,
+}
+
+impl<W: Write> SvgRenderer<W> {
+    pub fn new(svg: SvgWriter<W>) -> Self {
+        Self { svg, result: Ok(()) }
+    }
+
+    ///Write to the svg, unless an earlier write failed
+    fn write(&mut self, write: impl FnOnce(&mut SvgWriter<W>) -> std::fmt::Result) {
+        if self.result.is_ok() {
+            self.result = write(&mut self.svg);
+        }
+    }
+}
+
+impl<W: Write> Renderer for SvgRenderer<W> {
+    type Output = Result<W, std::fmt::Error>;
+
+    fn begin(&mut self, frame: &Frame) {
+        self.write(|svg| svg.start_document(frame));
+    }
+
+    fn begin_group(&mut self, transform: &RelativeTransform) {
+        let transform = transform.svg_transform(self.svg.precision);
+        self.write(|svg| match transform {
+            Some(transform) => svg.start_element("g", &[("transform", &transform)]),
+            None => svg.start_element("g", &[]),
+        });
+    }
+
+    fn end_group(&mut self) {
+        self.write(|svg| svg.end_element());
+    }
+
+    fn draw_primitive(&mut self, shape: &RenderShape) {
+        self.write(|svg| svg.write_shape(shape));
+    }
+
+    fn finish(mut self) -> Self::Output {
+        self.write(|svg| svg.end_document());
+        self.result.map(|()| self.svg.into_inner())
+    }
+}
+
+///Defines shared groups in `<defs>` and draws them with `<use>` elements
+impl<W: Write> InstancingRenderer for SvgRenderer<W> {
+    fn begin_definitions(&mut self) {
+        self.write(|svg| svg.start_element("defs", &[]));
+    }
+
+    fn end_definitions(&mut self) {
+        self.write(|svg| svg.end_element());
+    }
+
+    fn begin_definition(&mut self, id: usize) {
+        self.write(|svg| svg.start_element("g", &[("id", &format!("g{id}"))]));
+    }
+
+    fn end_definition(&mut self) {
+        self.write(|svg| svg.end_element());
+    }
+
+    fn use_definition(&mut self, id: usize, transform: &RelativeTransform) {
+        let href = format!("#g{id}");
+        let transform = transform.svg_transform(self.svg.precision);
+        self.write(|svg| {
+            let mut attributes: Vec<(&str, &dyn Display)> = vec![("xlink:href", &href)];
+            if let Some(transform) = &transform {
+                attributes.push(("transform", transform));
+            }
+            svg.empty_element("use", &attributes)
+        });
+    }
+}
+
+impl NodeTree {
+    pub fn to_svg(&self, grammar: &CompiledGrammar) -> String {
+        self.to_svg_with(grammar, &SvgSettings::default())
+    }
+
+    pub fn to_svg_with(&self, grammar: &CompiledGrammar, settings: &SvgSettings) -> String {
+        let mut svg = String::new();
+        //Writing to a string cannot fail
+        let _ = self.write_svg(grammar, settings, &mut svg);
+        svg
+    }
+
+    pub fn write_svg<W: Write>(&self, grammar: &CompiledGrammar, settings: &SvgSettings, writer: W) -> std::fmt::Result {
+        let mut svg = SvgWriter::new(writer).with_precision(settings.precision);
+        if settings.css_classes {
+            for node in self.nodes.iter().filter(|n| !n.is_group()) {
+                if let CompiledMethod::Primitive(_) = grammar.get_invocation(node.invocation).method {
+                    svg.add_class(&Rgba::from_properties(&node.absolute_properties));
+                }
+            }
+        }
+        if settings.instancing {
+            let precision = settings.precision;
+            let instances = self.find_instances(
+                |node| {
+                    let mut leaf = SvgWriter::new(String::new()).with_precision(precision);
+                    if let Some(shape) = node.render_shape(grammar) {
+                        let _ = leaf.write_shape(&shape);
+                    }
+                    leaf.into_inner()
+                },
+                |transform| transform.svg_transform(precision),
+            );
+            self.render_instanced(grammar, &instances, SvgRenderer::new(svg)).map(|_| ())
+        } else {
+            self.render(grammar, SvgRenderer::new(svg)).map(|_| ())
+        }
+    }
+}
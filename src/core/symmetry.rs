@@ -0,0 +1,377 @@
+use std::str::FromStr;
+
+use crate::core::prelude::*;
+use itertools::Itertools;
+use pest::iterators::Pair;
+use rand::prelude::StdRng;
+use serde::{Deserialize, Serialize};
+
+///The most copies a symmetry can make of an invocation, so that a small wallpaper cell cannot make too many nodes
+pub const MAX_SYMMETRY_COPIES: usize = 4096;
+
+///The most lattice cells in each direction from the centre that a wallpaper group is drawn in
+const MAX_CELLS: i32 = 16;
+
+///A rotation by `r` degrees, after a reflection in the vertical axis if `mirror` is set, followed by a translation
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Isometry {
+    pub r: f32,
+    pub mirror: bool,
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Isometry {
+    pub const IDENTITY: Isometry = Isometry::rotation(0.0);
+
+    pub const fn rotation(r: f32) -> Self {
+        Self {
+            r,
+            mirror: false,
+            x: 0.0,
+            y: 0.0,
+        }
+    }
+
+    ///A reflection in the vertical axis followed by a rotation
+    pub const fn reflection(r: f32) -> Self {
+        Self {
+            r,
+            mirror: true,
+            x: 0.0,
+            y: 0.0,
+        }
+    }
+
+    pub fn translated(self, x: f32, y: f32) -> Self {
+        Self {
+            x: self.x + x,
+            y: self.y + y,
+            ..self
+        }
+    }
+
+    ///Move a node placed by these relative properties, whose ranges must already be resolved
+    pub fn apply(&self, properties: &NodeProperties) -> NodeProperties {
+        let (x, r) = if self.mirror {
+            (-properties.x.value(), -properties.r.value())
+        } else {
+            (properties.x.value(), properties.r.value())
+        };
+        let y = properties.y.value();
+        let (sin, cos) = self.r.to_radians().sin_cos();
+
+        NodeProperties {
+            x: (cos * x - sin * y + self.x).into(),
+            y: (sin * x + cos * y + self.y).into(),
+            r: (self.r + r).into(),
+            mirrored: properties.mirrored ^ self.mirror,
+            ..properties.clone()
+        }
+    }
+}
+
+///Rotations by multiples of `360 / order` degrees
+fn rotations(order: usize) -> Vec<Isometry> {
+    (0..order)
+        .map(|i| Isometry::rotation(360.0 * i as f32 / order as f32))
+        .collect_vec()
+}
+
+///The wallpaper groups, named as in the international notation
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum WallpaperGroup {
+    P1,
+    P2,
+    Pm,
+    Pg,
+    Cm,
+    Pmm,
+    Pmg,
+    Pgg,
+    Cmm,
+    P4,
+    P4m,
+    P4g,
+    P3,
+    P3m1,
+    P31m,
+    P6,
+    P6m,
+}
+
+impl FromStr for WallpaperGroup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "p1" => Ok(WallpaperGroup::P1),
+            "p2" => Ok(WallpaperGroup::P2),
+            "pm" => Ok(WallpaperGroup::Pm),
+            "pg" => Ok(WallpaperGroup::Pg),
+            "cm" => Ok(WallpaperGroup::Cm),
+            "pmm" => Ok(WallpaperGroup::Pmm),
+            "pmg" => Ok(WallpaperGroup::Pmg),
+            "pgg" => Ok(WallpaperGroup::Pgg),
+            "cmm" => Ok(WallpaperGroup::Cmm),
+            "p4" => Ok(WallpaperGroup::P4),
+            "p4m" => Ok(WallpaperGroup::P4m),
+            "p4g" => Ok(WallpaperGroup::P4g),
+            "p3" => Ok(WallpaperGroup::P3),
+            "p3m1" => Ok(WallpaperGroup::P3m1),
+            "p31m" => Ok(WallpaperGroup::P31m),
+            "p6" => Ok(WallpaperGroup::P6),
+            "p6m" => Ok(WallpaperGroup::P6m),
+            _ => Err(format!("'{}' is not a wallpaper group", s)),
+        }
+    }
+}
+
+impl WallpaperGroup {
+    ///Whether the lattice is made of rhombi with angles of 60 and 120 degrees rather than of squares
+    fn is_hexagonal(&self) -> bool {
+        matches!(
+            self,
+            WallpaperGroup::P3
+                | WallpaperGroup::P3m1
+                | WallpaperGroup::P31m
+                | WallpaperGroup::P6
+                | WallpaperGroup::P6m
+        )
+    }
+
+    ///Whether the cell has a copy at its centre as well as at its corners
+    fn is_centred(&self) -> bool {
+        matches!(self, WallpaperGroup::Cm | WallpaperGroup::Cmm)
+    }
+
+    ///The operations which leave the origin, or for glides the origin's cell, where it is
+    fn point_operations(&self, cell: f32) -> Vec<Isometry> {
+        use Isometry as I;
+        let half = cell / 2.0;
+        //Reflections in the vertical and in the horizontal axis
+        let (mirror_x, mirror_y) = (I::reflection(0.0), I::reflection(180.0));
+
+        match self {
+            WallpaperGroup::P1 => vec![I::IDENTITY],
+            WallpaperGroup::P2 => vec![I::IDENTITY, I::rotation(180.0)],
+            WallpaperGroup::Pm | WallpaperGroup::Cm => vec![I::IDENTITY, mirror_x],
+            WallpaperGroup::Pg => vec![I::IDENTITY, mirror_x.translated(0.0, half)],
+            WallpaperGroup::Pmm | WallpaperGroup::Cmm => {
+                vec![I::IDENTITY, I::rotation(180.0), mirror_x, mirror_y]
+            }
+            WallpaperGroup::Pmg => vec![
+                I::IDENTITY,
+                I::rotation(180.0),
+                mirror_x.translated(half, 0.0),
+                mirror_y.translated(half, 0.0),
+            ],
+            WallpaperGroup::Pgg => vec![
+                I::IDENTITY,
+                I::rotation(180.0),
+                mirror_x.translated(half, half),
+                mirror_y.translated(half, half),
+            ],
+            WallpaperGroup::P4 => rotations(4),
+            WallpaperGroup::P4m => rotations(4)
+                .into_iter()
+                .chain((0..4).map(|i| I::reflection(90.0 * i as f32)))
+                .collect_vec(),
+            WallpaperGroup::P4g => rotations(4)
+                .into_iter()
+                .chain((0..4).map(|i| I::reflection(90.0 * i as f32).translated(half, half)))
+                .collect_vec(),
+            WallpaperGroup::P3 => rotations(3),
+            //A reflection in the line at an angle `a` is a rotation by `2a + 180` after a reflection in the vertical axis.
+            //The mirrors of p3m1 are perpendicular to the sides of the cell and those of p31m are along them
+            WallpaperGroup::P3m1 => rotations(3)
+                .into_iter()
+                .chain([0.0, 120.0, 240.0].map(I::reflection))
+                .collect_vec(),
+            WallpaperGroup::P31m => rotations(3)
+                .into_iter()
+                .chain([60.0, 180.0, 300.0].map(I::reflection))
+                .collect_vec(),
+            WallpaperGroup::P6 => rotations(6),
+            WallpaperGroup::P6m => rotations(6)
+                .into_iter()
+                .chain((0..6).map(|i| I::reflection(60.0 * i as f32)))
+                .collect_vec(),
+        }
+    }
+
+    ///The operations which repeat the contents of one lattice cell over the whole cell, including any copy at its centre.
+    ///Together with the translations of the lattice they make the group.
+    pub fn cell_operations(&self, cell: f32) -> Vec<Isometry> {
+        let mut operations = self.point_operations(cell);
+        if self.is_centred() {
+            let centred = operations
+                .iter()
+                .map(|i| i.translated(cell / 2.0, cell / 2.0))
+                .collect_vec();
+            operations.extend(centred);
+        }
+        operations
+    }
+
+    ///The two vectors whose whole multiples are the translations of the lattice
+    pub fn lattice(&self, cell: f32) -> [(f32, f32); 2] {
+        if self.is_hexagonal() {
+            [(cell, 0.0), (cell / 2.0, cell * 3f32.sqrt() / 2.0)]
+        } else {
+            [(cell, 0.0), (0.0, cell)]
+        }
+    }
+
+    ///The translations of the lattice which are no further than `cover` from the origin, nearest first
+    fn translations(&self, cell: f32, cover: f32) -> Vec<(f32, f32)> {
+        let [a, b] = self.lattice(cell);
+        //Hexagonal lattices are skewed, so they need more cells along their sides to cover the same distance
+        let cells = ((2.0 * cover / cell).ceil() as i32 + 1).min(MAX_CELLS);
+
+        (-cells..=cells)
+            .cartesian_product(-cells..=cells)
+            .map(|(i, j)| {
+                let (i, j) = (i as f32, j as f32);
+                (i * a.0 + j * b.0, i * a.1 + j * b.1)
+            })
+            .filter(|(x, y)| x.hypot(*y) <= cover)
+            .sorted_by(|l, r| l.0.hypot(l.1).total_cmp(&r.0.hypot(r.1)))
+            .collect_vec()
+    }
+}
+
+///A symmetry of an invocation, whose subtree is drawn once for each operation of the group
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Symmetry {
+    ///Rotations about the parent's centre by multiples of `360 / n` degrees
+    Rotation(usize),
+    ///A reflection in the parent's vertical axis
+    Mirror,
+    ///The rotations of `Rotation` with a mirror through each
+    Dihedral(usize),
+    ///A pattern repeated over the whole canvas, on a lattice whose cells have sides of length `cell`
+    Wallpaper { group: WallpaperGroup, cell: f32 },
+}
+
+impl Symmetry {
+    pub fn try_parse(symmetry: Pair<Rule>) -> Result<Self, String> {
+        //The first pair is the keyword
+        let group = symmetry.into_inner().nth(1).unwrap();
+        let rule = group.as_rule();
+        let mut inner = group.into_inner();
+        let number = |pair: Pair<Rule>| pair.as_str().parse::<f32>().unwrap();
+
+        match rule {
+            Rule::rotation_group | Rule::dihedral_group => {
+                let order = number(inner.next().unwrap());
+                if order < 1.0 || order.fract() != 0.0 {
+                    return Err(format!(
+                        "Symmetry order must be a whole number of at least 1, not {}",
+                        order
+                    ));
+                }
+                if rule == Rule::rotation_group {
+                    Ok(Symmetry::Rotation(order as usize))
+                } else {
+                    Ok(Symmetry::Dihedral(order as usize))
+                }
+            }
+            Rule::mirror_group => Ok(Symmetry::Mirror),
+            Rule::wallpaper_group => {
+                let group = WallpaperGroup::from_str(inner.next().unwrap().as_str())?;
+                let cell = inner.next().map(number).unwrap_or(1.0);
+                if cell <= 0.0 {
+                    return Err("Symmetry cell size must be more than 0".to_string());
+                }
+                Ok(Symmetry::Wallpaper { group, cell })
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    ///Whether this repeats over the whole plane rather than staying near the parent
+    pub fn is_wallpaper(&self) -> bool {
+        matches!(self, Symmetry::Wallpaper { .. })
+    }
+
+    ///The operations of this group, in the units of the parent.
+    ///The translations of a wallpaper group go as far as `cover` from the parent's centre.
+    pub fn operations(&self, cover: f32) -> Vec<Isometry> {
+        match self {
+            Symmetry::Rotation(order) => rotations(*order),
+            Symmetry::Mirror => vec![Isometry::IDENTITY, Isometry::reflection(0.0)],
+            Symmetry::Dihedral(order) => {
+                let rotations = rotations(*order);
+                let reflections = rotations
+                    .iter()
+                    .map(|i| Isometry::reflection(i.r))
+                    .collect_vec();
+                rotations.into_iter().chain(reflections).collect_vec()
+            }
+            Symmetry::Wallpaper { group, cell } => {
+                let operations = group.cell_operations(*cell);
+                //Copies up to a couple of cells beyond the cover may still reach into it
+                group
+                    .translations(*cell, cover + 2.0 * cell)
+                    .into_iter()
+                    .flat_map(|(x, y)| operations.iter().map(move |i| i.translated(x, y)))
+                    .take(MAX_SYMMETRY_COPIES)
+                    .collect_vec()
+            }
+        }
+    }
+}
+
+impl CompiledGrammar {
+    ///How far from the centre of a node, in the node's units, the frame reaches
+    fn symmetry_cover(&self, properties: &NodeProperties) -> f32 {
+        let scale = properties.p.value().abs();
+        if scale == 0.0 {
+            return 0.0;
+        }
+        let bounds = self.frame.bounds;
+        let (x, y) = (properties.x.value(), properties.y.value());
+        let dx = (x - bounds.min_x).abs().max((bounds.max_x - x).abs());
+        let dy = (y - bounds.min_y).abs().max((bounds.max_y - y).abs());
+        dx.hypot(dy) / scale
+    }
+
+    ///Create the nodes for an invocation, one for each operation of its symmetry and of the outer symmetry.
+    ///Every copy has the same seed and the same random properties, so their subtrees are images of each other.
+    pub fn to_nodes(
+        &self,
+        id: InvocationId,
+        parent_properties: &NodeProperties,
+        outer: Option<&Symmetry>,
+        seed: u64,
+        rng: &mut StdRng,
+    ) -> Vec<Node> {
+        let inner = self.get_invocation(id).symmetry.as_ref();
+        if inner.is_none() && outer.is_none() {
+            return vec![self.to_node(id, parent_properties, seed, rng)];
+        }
+
+        let relative_properties = self
+            .relative_properties(id, parent_properties, rng)
+            .sample(rng);
+        let cover = self.symmetry_cover(parent_properties);
+        let operations = |symmetry: Option<&Symmetry>| match symmetry {
+            Some(symmetry) => symmetry.operations(cover),
+            None => vec![Isometry::IDENTITY],
+        };
+        let inner = operations(inner);
+        let relative_properties = &relative_properties;
+
+        operations(outer)
+            .iter()
+            .flat_map(|o| {
+                inner
+                    .iter()
+                    .map(move |i| o.apply(&i.apply(relative_properties)))
+            })
+            .take(MAX_SYMMETRY_COPIES)
+            .map(|properties| self.place_node(id, parent_properties, &properties, seed))
+            .collect_vec()
+    }
+}
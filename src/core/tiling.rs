@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+
+use crate::core::prelude::*;
+
+impl Bounds {
+    ///Whether these bounds overlap the other by more than an edge
+    fn overlaps(&self, other: &Self) -> bool {
+        self.min_x < other.max_x
+            && other.min_x < self.max_x
+            && self.min_y < other.max_y
+            && other.min_y < self.max_y
+    }
+
+    fn translate(self, dx: f32, dy: f32) -> Self {
+        Self::new(
+            self.min_x + dx,
+            self.min_y + dy,
+            self.max_x + dx,
+            self.max_y + dy,
+        )
+    }
+}
+
+impl NodeTree {
+    ///The offsets at which copies of this leaf must be drawn so that the canvas wraps around like a torus
+    fn tile_offsets(
+        &self,
+        index: usize,
+        grammar: &CompiledGrammar,
+        canvas: &Bounds,
+    ) -> Vec<(f32, f32)> {
+        let node = &self.nodes[index];
+        let bounds = match grammar.get_invocation(node.invocation).method {
+            CompiledMethod::Primitive(primitive) => primitive.get_bounds(&node.absolute_properties),
+            CompiledMethod::Rule(_) | CompiledMethod::Root => return vec![],
+        };
+        let (width, height) = (canvas.width(), canvas.height());
+
+        let mut offsets = vec![];
+        for dx in [-width, 0.0, width] {
+            for dy in [-height, 0.0, height] {
+                if (dx, dy) != (0.0, 0.0) && bounds.translate(dx, dy).overlaps(canvas) {
+                    offsets.push((dx, dy));
+                }
+            }
+        }
+        offsets
+    }
+
+    ///This tree drawn on a tile which repeats seamlessly, treating the canvas as a torus.
+    ///Each shape crossing an edge of the canvas is copied to the opposite edge, and to the opposite corner if it crosses two edges.
+    ///Each copy is drawn straight after the shape it copies, inside the same group.
+    pub fn tiled(&self, grammar: &CompiledGrammar) -> NodeTree {
+        let canvas = self.frame(grammar).bounds;
+        let mut nodes = vec![self.nodes[Self::ROOT].clone()];
+        //Pairs of the index of an original node and of its copy in the new tree, whose children are still to be added
+        let mut queue = VecDeque::from([(Self::ROOT, Self::ROOT)]);
+
+        //Children are added breadth first so that every node's children are still contiguous and after it
+        while let Some((original, index)) = queue.pop_front() {
+            let children = match self.nodes[original].children.clone() {
+                Some(children) if !children.is_empty() => children,
+                _ => continue,
+            };
+            let parent = &self.nodes[original].absolute_properties;
+            let (sin, cos) = parent.r.value().to_radians().sin_cos();
+            let scale = parent.p.value();
+
+            let start = nodes.len();
+            for child in children {
+                queue.push_back((child, nodes.len()));
+                nodes.push(self.nodes[child].clone());
+
+                for (dx, dy) in self.tile_offsets(child, grammar, &canvas) {
+                    let mut copy = self.nodes[child].clone();
+                    copy.absolute_properties.x = copy.absolute_properties.x + dx.into();
+                    copy.absolute_properties.y = copy.absolute_properties.y + dy.into();
+                    //The offset is in absolute coordinates, so undo the rotation and scale of the parent
+                    if scale != 0.0 {
+                        copy.transform.x += (cos * dx + sin * dy) / scale;
+                        copy.transform.y += (cos * dy - sin * dx) / scale;
+                    }
+                    nodes.push(copy);
+                }
+            }
+            nodes[index].children = Some(start..nodes.len());
+        }
+
+        NodeTree {
+            nodes,
+            statistics: self.statistics,
+            profile: self.profile.clone(),
+        }
+    }
+
+    ///Preview the tile made by `tiled` as an svg of a three by three grid of tiles
+    pub fn to_tile_preview_svg(&self, grammar: &CompiledGrammar) -> String {
+        let tile = self.tiled(grammar);
+        let frame = self.frame(grammar);
+        let canvas = frame.bounds;
+        let (width, height) = (canvas.width(), canvas.height());
+
+        let mut svg = SvgWriter::new(String::new());
+        //Writing to a string cannot fail
+        let _ = (|| -> std::fmt::Result {
+            svg.start_document(&Frame {
+                bounds: canvas
+                    .translate(-width, -height)
+                    .union(canvas.translate(width, height)),
+                background: frame.background,
+            })?;
+
+            svg.start_element("defs", &[])?;
+            svg.start_element("clipPath", &[("id", &"tile-clip")])?;
+            svg.empty_element(
+                "rect",
+                &[
+                    ("x", &svg.number(canvas.min_x)),
+                    ("y", &svg.number(canvas.min_y)),
+                    ("width", &svg.number(width)),
+                    ("height", &svg.number(height)),
+                ],
+            )?;
+            svg.end_element()?;
+            svg.start_element("g", &[("id", &"tile"), ("clip-path", &"url(#tile-clip)")])?;
+            //Shapes are drawn in absolute coordinates, without the groups of the tree
+            for index in tile.leaves_in_draw_order() {
+                if let Some(shape) = tile.nodes[index].render_shape(grammar) {
+                    svg.write_shape(&RenderShape {
+                        group_transform: shape.transform,
+                        ..shape
+                    })?;
+                }
+            }
+            svg.end_element()?;
+            svg.end_element()?;
+
+            for dx in [-width, 0.0, width] {
+                for dy in [-height, 0.0, height] {
+                    let transform = format!("translate({} {})", svg.number(dx), svg.number(dy));
+                    svg.empty_element(
+                        "use",
+                        &[("xlink:href", &"#tile"), ("transform", &transform)],
+                    )?;
+                }
+            }
+            svg.end_document()
+        })();
+        svg.into_inner()
+    }
+}
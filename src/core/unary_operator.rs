@@ -32,10 +32,34 @@ impl FromStr for UnaryOperator {
 }
 
 impl UnaryOperator {
+    ///Apply this operator to every value in the range
+    pub fn apply_range(self, value: ValueOrRange) -> ValueOrRange {
+        match value {
+            ValueOrRange::Value(v) => ValueOrRange::Value(self.apply(v)),
+            _ => value.map_intervals(|i| match self {
+                UnaryOperator::Sub => vec![Interval::new(-i.end, -i.start)],
+                UnaryOperator::Abs => {
+                    if i.contains(0.0) {
+                        vec![Interval::new(0.0, i.start.abs().max(i.end.abs()))]
+                    } else {
+                        vec![Interval::new(i.start.abs(), i.end.abs())]
+                    }
+                }
+                UnaryOperator::Sig => [(i.start < 0.0, -1.0), (i.contains(0.0), 0.0), (i.end > 0.0, 1.0)]
+                    .into_iter()
+                    .filter(|(possible, _)| *possible)
+                    .map(|(_, sign)| Interval::point(sign))
+                    .collect_vec(),
+            }),
+        }
+    }
+
     pub fn apply(self, value: f32) -> f32 {
         match self {
             UnaryOperator::Sub => -value,
             UnaryOperator::Abs => value.abs(),
+            //`f32::signum` is 1 at zero, which ranges containing zero would never give
+            UnaryOperator::Sig if value == 0.0 => 0.0,
             UnaryOperator::Sig => value.signum(),
         }
     }
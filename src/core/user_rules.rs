@@ -21,25 +21,3 @@ pub struct RuleCase {
     pub probability: Option<Expression>,
     pub invocations: Vec<Invocation>,
 }
-
-impl RuleCase {
-    pub fn should_enter(
-        &self,
-        grammar: &Grammar,
-        context: &NodeProperties,
-        rng: &mut StdRng,
-    ) -> bool {
-        if let Some(value) = &self.probability {
-            let prob = value.try_get_value(grammar, context, rng).unwrap();
-            if prob.min_value() >= 1.0 {
-                true
-            } else if prob.max_value() <= 0.0 {
-                false
-            } else {
-                rng.gen_bool(prob.max_value().into())//todo look at this
-            }
-        } else {
-            true
-        }
-    }
-}
@@ -11,22 +11,71 @@ use rand::prelude::StdRng;
 use serde::{Deserialize, Serialize};
 
 
-#[derive(PartialEq, PartialOrd, Clone, Copy)]
+///A closed interval with `start <= end`
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug, Default)]
+pub struct Interval {
+    pub start: f32,
+    pub end: f32,
+}
+
+impl Interval {
+    pub fn new(a: f32, b: f32) -> Self {
+        if b < a {
+            Self { start: b, end: a }
+        } else {
+            Self { start: a, end: b }
+        }
+    }
+
+    pub fn point(v: f32) -> Self {
+        Self { start: v, end: v }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, v: f32) -> bool {
+        self.start <= v && v <= self.end
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        self.start.is_finite() && self.end.is_finite()
+    }
+}
+
+///The most intervals a compound range can hold.
+///Unions of more intervals are merged across their smallest gaps, so they over-approximate the union and include the values in those gaps.
+pub const MAX_INTERVALS: usize = 3;
+
+///A union of disjoint intervals, in order. It is stored inline so that `ValueOrRange` can be `Copy`.
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug)]
+pub struct CompoundRange {
+    len: u8,
+    intervals: [Interval; MAX_INTERVALS],
+}
+
+impl CompoundRange {
+    pub fn intervals(&self) -> &[Interval] {
+        &self.intervals[..self.len as usize]
+    }
+}
+
+#[derive(PartialEq, PartialOrd, Clone, Copy, Debug)]
 pub enum ValueOrRange{
     Value(f32),
-    Range{start: f32, end: f32}    
+    Range{start: f32, end: f32},
+    ///A union of at least two disjoint intervals
+    Compound(CompoundRange),
 }
 
 impl Add for ValueOrRange{
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
-        match self{
-            ValueOrRange::Value(v) => rhs.apply(|x| x +v),
-            ValueOrRange::Range { start, end } => match rhs {
-                ValueOrRange::Value(v) => Self::Range { start: start + v, end: end + v },
-                ValueOrRange::Range { start:s2, end:e2 } => Self::Range { start: start + s2, end: end + e2 },
-            },
+        match (self, rhs) {
+            (ValueOrRange::Value(l), ValueOrRange::Value(r)) => ValueOrRange::Value(l + r),
+            _ => BinaryOperator::Add.apply_range(self, rhs),
         }
     }
 }
@@ -35,12 +84,9 @@ impl Sub for ValueOrRange{
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        match self{
-            ValueOrRange::Value(v) => rhs.apply(|x| v - x),
-            ValueOrRange::Range { start, end } => match rhs {
-                ValueOrRange::Value(v) => Self::Range { start: start - v, end: end - v },
-                ValueOrRange::Range { start:s2, end:e2 } => Self::Range { start: start - s2, end: end - e2 },
-            },
+        match (self, rhs) {
+            (ValueOrRange::Value(l), ValueOrRange::Value(r)) => ValueOrRange::Value(l - r),
+            _ => BinaryOperator::Sub.apply_range(self, rhs),
         }
     }
 }
@@ -49,12 +95,9 @@ impl Mul for ValueOrRange{
     type Output = Self;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        match self{
-            ValueOrRange::Value(v) => rhs.apply(|x| x * v),
-            ValueOrRange::Range { start, end } => match rhs {
-                ValueOrRange::Value(v) => Self::Range { start: start * v, end: end * v },
-                ValueOrRange::Range { start:s2, end:e2 } => Self::Range { start: start * s2, end: end * e2 },
-            },
+        match (self, rhs) {
+            (ValueOrRange::Value(l), ValueOrRange::Value(r)) => ValueOrRange::Value(l * r),
+            _ => BinaryOperator::Mul.apply_range(self, rhs),
         }
     }
 }
@@ -73,33 +116,140 @@ impl From<f32> for ValueOrRange{
 
 impl ValueOrRange{
 
+    ///The union of some intervals. Intervals containing NaN are ignored.
+    ///If there are more than `MAX_INTERVALS` disjoint intervals the result also contains the smallest gaps between them.
+    pub fn from_intervals(intervals: impl IntoIterator<Item = Interval>) -> Self {
+        let sorted = intervals
+            .into_iter()
+            .filter(|i| !i.start.is_nan() && !i.end.is_nan())
+            .sorted_by(|a, b| a.start.partial_cmp(&b.start).unwrap());
+
+        let mut merged: Vec<Interval> = Vec::new();
+        for interval in sorted {
+            match merged.last_mut() {
+                Some(last) if interval.start <= last.end => last.end = last.end.max(interval.end),
+                _ => merged.push(interval),
+            }
+        }
+
+        while merged.len() > MAX_INTERVALS {
+            let (i, _) = merged
+                .iter()
+                .tuple_windows()
+                .map(|(a, b)| b.start - a.end)
+                .enumerate()
+                .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+                .unwrap();
+            merged[i].end = merged[i + 1].end;
+            merged.remove(i + 1);
+        }
+
+        match merged.as_slice() {
+            [] => ValueOrRange::Value(f32::NAN),
+            [i] if i.start == i.end => ValueOrRange::Value(i.start),
+            [i] => ValueOrRange::Range { start: i.start, end: i.end },
+            _ => {
+                let mut intervals = [Interval::default(); MAX_INTERVALS];
+                intervals[..merged.len()].copy_from_slice(&merged);
+                ValueOrRange::Compound(CompoundRange {
+                    len: merged.len() as u8,
+                    intervals,
+                })
+            }
+        }
+    }
+
+    ///The union of two values or ranges
+    pub fn union(self, other: Self) -> Self {
+        Self::from_intervals(self.intervals().into_iter().chain(other.intervals()))
+    }
+
+    ///The intervals making up this value or range, in order
+    pub fn intervals(self) -> Vec<Interval> {
+        match self {
+            ValueOrRange::Value(v) => vec![Interval::point(v)],
+            ValueOrRange::Range { start, end } => vec![Interval::new(start, end)],
+            ValueOrRange::Compound(c) => c.intervals().to_vec(),
+        }
+    }
+
+    ///Apply a function to each interval of this value or range
+    pub fn map_intervals<I: IntoIterator<Item = Interval>>(self, f: impl Fn(Interval) -> I) -> Self {
+        Self::from_intervals(self.intervals().into_iter().flat_map(f))
+    }
+
+    ///A random value from this range.
+    ///Values from compound ranges are chosen uniformly by length, which includes any gaps merged by `from_intervals`.
+    ///Unbounded intervals can not be sampled uniformly so only their finite ends are ever chosen.
     pub fn random_value(self, rng: &mut StdRng)->f32{
         match self{
             ValueOrRange::Value(v) => v,
-            ValueOrRange::Range { start, end } => 
+            ValueOrRange::Range { start, end } =>
             if start<=end{rng.gen_range(start..=end)} else{rng.gen_range(end..=start)}
             ,
+            ValueOrRange::Compound(c) => {
+                let bounded = c.intervals().iter().filter(|i| i.is_bounded()).collect_vec();
+                let total: f32 = bounded.iter().map(|i| i.length()).sum();
+
+                if total > 0.0 && total.is_finite() {
+                    let mut t = rng.gen_range(0.0..total);
+                    for i in bounded.iter() {
+                        if t <= i.length() {
+                            return i.start + t;
+                        }
+                        t -= i.length();
+                    }
+                    bounded[bounded.len() - 1].end
+                } else if !bounded.is_empty() {
+                    let i = bounded[rng.gen_range(0..bounded.len())];
+                    rng.gen_range(i.start..=i.end)
+                } else {
+                    let i = c.intervals()[rng.gen_range(0..c.intervals().len())];
+                    if i.start.is_finite() { i.start } else if i.end.is_finite() { i.end } else { 0.0 }
+                }
+            }
+        }
+    }
+
+    ///The value, or the mean of a value chosen uniformly from the range
+    pub fn value(self)-> f32{
+        match self{
+            ValueOrRange::Value(v) => v,
+            ValueOrRange::Range { start, end } => (start + end) / 2.0,
+            ValueOrRange::Compound(c) => {
+                let total: f32 = c.intervals().iter().map(|i| i.length()).sum();
+                if total > 0.0 {
+                    c.intervals().iter().map(|i| i.length() * (i.start + i.end) / 2.0).sum::<f32>() / total
+                } else {
+                    c.intervals().iter().map(|i| i.start).sum::<f32>() / c.intervals().len() as f32
+                }
+            }
         }
     }
 
     pub fn max_abs(self)-> f32{
         match self{
             ValueOrRange::Value(v) => v.abs(),
-            ValueOrRange::Range { start, end } => start.abs().max(end.abs()),
+            _ => self.min_value().abs().max(self.max_value().abs()),
         }
     }
     
     pub fn min_abs(self)-> f32{
         match self{
             ValueOrRange::Value(v) => v.abs(),
-            ValueOrRange::Range { start, end } => start.abs().min(end.abs()),
+            _ => self
+                .intervals()
+                .into_iter()
+                .map(|i| if i.contains(0.0) { 0.0 } else { i.start.abs().min(i.end.abs()) })
+                .fold(f32::INFINITY, f32::min),
         }
     }
 
+    ///Apply a function which never decreases
     fn apply(self, f: impl Fn(f32) -> f32)-> Self{
         match self{
             ValueOrRange::Value(v) => f(v).into(),
-            ValueOrRange::Range { start, end } => ValueOrRange::Range { start:f(start), end:f(end) },
+            _ => self.map_intervals(|i| [Interval::new(f(i.start), f(i.end))]),
         }
     }
 
@@ -116,21 +266,60 @@ impl ValueOrRange{
     }
 
     pub fn cos_degrees(self)-> Self{
-        self.apply(|x|x.to_radians().cos())
+        match self{
+            ValueOrRange::Value(v) => v.to_radians().cos().into(),
+            _ => self.map_intervals(|i| [Self::periodic_image(i, |x| x.to_radians().cos(), 0.0, 180.0)]),
+        }
     }
     
     pub fn sin_degrees(self)-> Self{
-        self.apply(|x|x.to_radians().sin())
+        match self{
+            ValueOrRange::Value(v) => v.to_radians().sin().into(),
+            _ => self.map_intervals(|i| [Self::periodic_image(i, |x| x.to_radians().sin(), 90.0, 270.0)]),
+        }
+    }
+
+    ///The image of an interval under a function with period 360 whose greatest value, 1, is at `max_at` and least, -1, at `min_at`
+    fn periodic_image(interval: Interval, f: impl Fn(f32) -> f32, max_at: f32, min_at: f32) -> Interval {
+        if interval.length() >= 360.0 || !interval.is_bounded() {
+            return Interval::new(-1.0, 1.0);
+        }
+        let reaches = |at: f32| {
+            let next = at + ((interval.start - at) / 360.0).ceil() * 360.0;
+            next <= interval.end
+        };
+        let (a, b) = (f(interval.start), f(interval.end));
+        Interval::new(
+            if reaches(min_at) { -1.0 } else { a.min(b) },
+            if reaches(max_at) { 1.0 } else { a.max(b) },
+        )
     }
 
     pub fn mod360(self)-> Self{
-        self.apply(|x|((x % 360.0)+ 360.0) % 360.0)
+        let mod360 = |x: f32| ((x % 360.0)+ 360.0) % 360.0;
+        match self{
+            ValueOrRange::Value(v) => mod360(v).into(),
+            _ => self.map_intervals(|i| {
+                if i.length() >= 360.0 || !i.is_bounded() {
+                    return vec![Interval::new(0.0, 360.0)];
+                }
+                let start = mod360(i.start);
+                let end = start + i.length();
+                if end < 360.0 {
+                    vec![Interval::new(start, end)]
+                } else {
+                    //The range wraps around
+                    vec![Interval::new(start, 360.0), Interval::new(0.0, end - 360.0)]
+                }
+            }),
+        }
     }
 
     pub fn min_value(self)-> f32{
         match self{
             ValueOrRange::Value(v) => v,
             ValueOrRange::Range { start, end } => start.min(end),
+            ValueOrRange::Compound(c) => c.intervals()[0].start,
         }
     }
     
@@ -138,8 +327,7 @@ impl ValueOrRange{
         match self{
             ValueOrRange::Value(v) => v,
             ValueOrRange::Range { start, end } => start.max(end),
+            ValueOrRange::Compound(c) => c.intervals()[c.intervals().len() - 1].end,
         }
     }
-
-
-}
\ No newline at end of file
+}
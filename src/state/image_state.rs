@@ -5,12 +5,18 @@ use num::ToPrimitive;
 use serde::*;
 use std::collections::BTreeMap;
 use std::default;
+use std::cell::RefCell;
 use std::rc::Rc;
 use yewdux::prelude::*;
 
 #[derive(PartialEq, Store, Clone, Serialize, Deserialize)]
 pub struct ImageState {
     pub svg: String,
+    pub shapes: Rc<Vec<CanvasShape>>,
+    ///The area the shapes are drawn in, when they are drawn on a canvas
+    pub frame: Frame,
+    pub statistics: ExpandStatistics,
+    pub profile: ExpansionProfile,
 }
 
 impl Default for ImageState {
@@ -19,6 +25,10 @@ impl Default for ImageState {
 
         let mut s = Self {
             svg: Default::default(),
+            shapes: Default::default(),
+            frame: Default::default(),
+            statistics: Default::default(),
+            profile: Default::default(),
         };
 
         s.update_svg(v.as_ref());
@@ -27,14 +37,70 @@ impl Default for ImageState {
     }
 }
 
+thread_local! {
+    //Lets moving a variable slider reuse the parts of the image which do not depend on that variable
+    static EXPANSION_CACHE: RefCell<ExpansionCache> = RefCell::new(ExpansionCache::default());
+    //The grammar compiled from the last source, which is only compiled again when the source changes
+    static COMPILED_GRAMMAR: RefCell<Option<(Grammar, CompiledGrammar)>> = RefCell::new(None);
+}
+
 impl ImageState {
     pub fn update_svg(&mut self, input: &InputState) {
         let mut rng = rand::SeedableRng::seed_from_u64(input.seed);
 
-        let mut override_grammar = input.grammar.clone();
-        override_grammar.override_defs(&input.overrides);
-        let node = override_grammar.expand(&input.settings, &mut rng);
-        let svg = node.to_svg(&override_grammar,&mut rng);
-        self.svg = svg;
+        COMPILED_GRAMMAR.with(|compiled| {
+            let mut compiled = compiled.borrow_mut();
+            if !matches!(&*compiled, Some((source, _)) if *source == input.grammar) {
+                //Compilation errors are reported by the input state
+                *compiled = input
+                    .grammar
+                    .compile()
+                    .ok()
+                    .map(|grammar| (input.grammar.clone(), grammar));
+            }
+
+            match compiled.as_mut() {
+                Some((source, grammar)) => {
+                    //Every variable is set, so that one whose override was removed goes back to its definition
+                    let mut variables = source.defs.clone();
+                    variables.extend(input.overrides.iter().map(|(k, v)| (k.clone(), *v)));
+                    grammar.override_variables(&variables);
+
+                    EXPANSION_CACHE.with(|cache| {
+                        let mut cache = cache.borrow_mut();
+                        let tree = grammar.expand_cached(&input.settings, &mut rng, &mut cache);
+                        self.show(grammar, tree, input.display_mode);
+                    });
+                }
+                None => {
+                    self.svg = Default::default();
+                    self.shapes = Default::default();
+                    self.frame = Default::default();
+                    self.statistics = Default::default();
+                    self.profile = Default::default();
+                }
+            }
+        });
+    }
+
+    fn show(&mut self, grammar: &CompiledGrammar, tree: &NodeTree, display_mode: DisplayMode) {
+        //Only make what will be shown
+        match display_mode {
+            DisplayMode::Svg => {
+                self.svg = tree.to_svg_with(grammar, &SvgSettings::COMPACT);
+                self.shapes = Default::default();
+            }
+            DisplayMode::Tiles => {
+                self.svg = tree.to_tile_preview_svg(grammar);
+                self.shapes = Default::default();
+            }
+            DisplayMode::Canvas => {
+                self.svg = Default::default();
+                self.shapes = tree.to_canvas_shapes(grammar).into();
+                self.frame = tree.frame(grammar);
+            }
+        }
+        self.statistics = tree.statistics;
+        self.profile = tree.profile.clone();
     }
 }
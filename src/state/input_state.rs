@@ -30,6 +30,18 @@ impl Default for SavedCreationsState {
     }
 }
 
+///How the image is shown
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum DisplayMode {
+    ///An svg document in an iframe
+    #[default]
+    Svg,
+    ///Shapes drawn directly on a canvas, which is faster when there are many of them
+    Canvas,
+    ///An svg of the canvas wrapped around as a seamless tile, repeated in a three by three grid
+    Tiles,
+}
+
 #[derive(PartialEq, Store, Clone, Serialize, Deserialize)]
 #[store(storage = "local")] // can also be "session"
 pub struct InputState {
@@ -38,6 +50,8 @@ pub struct InputState {
     pub grammar: Grammar,
     pub overrides: BTreeMap<String, f32>,
     pub settings: ExpandSettings,
+    #[serde(default)]
+    pub display_mode: DisplayMode,
     pub error: Option<String>,
     pub seed: u64,
 }
@@ -53,6 +67,7 @@ impl Default for InputState {
             grammar,
             overrides: Default::default(),
             settings: Default::default(),
+            display_mode: Default::default(),
             error: Default::default(),
             seed: 100,
         }
@@ -98,6 +113,37 @@ impl InputState {
         Dispatch::<ImageState>::new().reduce_mut(|state: &mut ImageState| state.update_svg(self));
     }
 
+    pub fn set_display_mode(&mut self, display_mode: DisplayMode) {
+        self.display_mode = display_mode;
+        Dispatch::<ImageState>::new().reduce_mut(|state: &mut ImageState| state.update_svg(self));
+    }
+
+    ///What to embed in exported files so that they can be opened again
+    pub fn embedded_source(&self) -> EmbeddedSource {
+        EmbeddedSource {
+            source: self.text.clone(),
+            seed: self.seed,
+            settings: self.settings,
+            overrides: self.overrides.clone(),
+        }
+    }
+
+    ///Open a picture again from the source embedded in it
+    pub fn import(&mut self, source: EmbeddedSource) {
+        self.text = source.source;
+        match parse(self.text.as_str()).and_then(|g| g.compile().map(|_| g)) {
+            Ok(grammar) => {
+                self.error = None;
+                self.grammar = grammar;
+            }
+            Err(error) => self.error = Some(error),
+        }
+        self.seed = source.seed;
+        self.settings = source.settings;
+        self.overrides = source.overrides;
+        Dispatch::<ImageState>::new().reduce_mut(|state: &mut ImageState| state.update_svg(self));
+    }
+
     pub fn use_creation(&mut self, name: String) {
         let saved = Dispatch::<SavedCreationsState>::new().get();
         let s = saved.creations.get(&name);
@@ -111,7 +157,8 @@ impl InputState {
     pub fn update_text(&mut self, new_text: String) {
         if self.text != new_text {
             self.text = new_text.clone();
-            let grammar_result = parse(new_text.as_str());
+            let grammar_result =
+                parse(new_text.as_str()).and_then(|g| g.compile().map(|_| g));
 
             match grammar_result {
                 Ok(grammar) => {
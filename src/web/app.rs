@@ -4,7 +4,7 @@ use crate::core::prelude::*;
 use crate::state::{self, prelude::*};
 use crate::web::prelude::*;
 use itertools::Itertools;
-use web_sys::{HtmlInputElement, HtmlSelectElement, HtmlTextAreaElement};
+use web_sys::{HtmlCanvasElement, HtmlInputElement, HtmlSelectElement, HtmlTextAreaElement};
 use yew::prelude::*;
 use yewdux::prelude::*;
 
@@ -30,6 +30,10 @@ pub fn app() -> Html {
             <summary>{"Settings"}</summary>
             <SettingsControl/>
             </details>
+            <details>
+            <summary>{"Statistics"}</summary>
+            <StatisticsPanel/>
+            </details>
 
 
         </div>
@@ -65,6 +69,7 @@ pub fn examples_select() -> Html {
 #[function_component(SettingsControl)]
 pub fn settings_control() -> Html {
     let settings = *use_selector(|state: &InputState| state.settings).as_ref();
+    let display_mode = *use_selector(|state: &InputState| state.display_mode).as_ref();
 
     let on_max_nodes_input =
         Dispatch::<InputState>::new().reduce_mut_callback_with(move |s, e: InputEvent| {
@@ -90,6 +95,52 @@ pub fn settings_control() -> Html {
             s.update_settings(new_settings);
         });
 
+    let on_max_milliseconds_input =
+        Dispatch::<InputState>::new().reduce_mut_callback_with(move |s, e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            let new_value = input.value();
+            //Zero or blank means no time limit
+            let max_milliseconds = new_value.parse().ok().filter(|&ms: &u32| ms > 0);
+            let new_settings = ExpandSettings {
+                max_milliseconds,
+                ..settings
+            };
+            s.update_settings(new_settings);
+        });
+
+    let on_cull_margin_input =
+        Dispatch::<InputState>::new().reduce_mut_callback_with(move |s, e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            let new_value = input.value();
+            let cull_margin = new_value.parse().unwrap_or_else(|_| default_cull_margin());
+            let new_settings = ExpandSettings {
+                cull_margin,
+                ..settings
+            };
+            s.update_settings(new_settings);
+        });
+
+    let on_occlusion_culling_input =
+        Dispatch::<InputState>::new().reduce_mut_callback_with(move |s, e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            let new_settings = ExpandSettings {
+                occlusion_culling: input.checked(),
+                ..settings
+            };
+            s.update_settings(new_settings);
+        });
+
+    let on_display_mode_input =
+        Dispatch::<InputState>::new().reduce_mut_callback_with(|s, e: InputEvent| {
+            let input: HtmlSelectElement = e.target_unchecked_into();
+            let display_mode = match input.value().as_str() {
+                "canvas" => DisplayMode::Canvas,
+                "tiles" => DisplayMode::Tiles,
+                _ => DisplayMode::Svg,
+            };
+            s.set_display_mode(display_mode);
+        });
+
     html!(
         <>
         <div class="slider">
@@ -100,6 +151,26 @@ pub fn settings_control() -> Html {
                     <code style="width:80px" >{"Max Depth"}</code>
                     <input style="width:80px" oninput={on_max_depth_input} type="number"  value={format!("{}",settings.max_depth )} min={4} max={40}  step={1} />
                 </div>
+                <div class="slider">
+                    <code style="width:80px" >{"Max Time (ms)"}</code>
+                    <input style="width:80px" oninput={on_max_milliseconds_input} type="number"  value={format!("{}",settings.max_milliseconds.unwrap_or_default() )} min={0} max={10000}  step={100} />
+                </div>
+                <div class="slider">
+                    <code style="width:80px" >{"Cull Margin"}</code>
+                    <input style="width:80px" oninput={on_cull_margin_input} type="number"  value={format!("{}",settings.cull_margin )} min={0} max={10}  step={0.1} />
+                </div>
+                <div class="slider">
+                    <code style="width:80px" >{"Hide Covered"}</code>
+                    <input oninput={on_occlusion_culling_input} type="checkbox" checked={settings.occlusion_culling} />
+                </div>
+                <div class="slider">
+                    <code style="width:80px" >{"Renderer"}</code>
+                    <select oninput={on_display_mode_input}>
+                    <option selected={display_mode == DisplayMode::Svg} value="svg">{"Svg"}</option>
+                    <option selected={display_mode == DisplayMode::Canvas} value="canvas">{"Canvas"}</option>
+                    <option selected={display_mode == DisplayMode::Tiles} value="tiles">{"Tiles"}</option>
+                    </select>
+                </div>
                 </>
 
 
@@ -247,6 +318,16 @@ pub fn erorr_box() -> Html {
 
 #[function_component(DisplayBox)]
 pub fn diplay_box() -> Html {
+    let display_mode = *use_selector(|s: &InputState| s.display_mode).as_ref();
+
+    match display_mode {
+        DisplayMode::Svg | DisplayMode::Tiles => html!(<SvgBox/>),
+        DisplayMode::Canvas => html!(<CanvasBox/>),
+    }
+}
+
+#[function_component(SvgBox)]
+pub fn svg_box() -> Html {
     let svg = use_selector(|s: &ImageState| s.svg.clone())
         .as_ref()
         .clone();
@@ -255,3 +336,82 @@ pub fn diplay_box() -> Html {
         <iframe class="display-iframe" srcdoc={svg} scrolling="no"></iframe>
     )
 }
+
+#[function_component(CanvasBox)]
+pub fn canvas_box() -> Html {
+    let image = use_selector(|s: &ImageState| (s.frame, s.shapes.clone()))
+        .as_ref()
+        .clone();
+    let canvas_ref = use_node_ref();
+
+    {
+        let canvas_ref = canvas_ref.clone();
+        use_effect_with_deps(
+            move |(frame, shapes)| {
+                if let Some(canvas) = canvas_ref.cast::<HtmlCanvasElement>() {
+                    //This only fails if the browser cannot draw on canvases at all
+                    let _ = draw_shapes(&canvas, frame, shapes);
+                }
+                || ()
+            },
+            image,
+        );
+    }
+
+    html!(
+        <canvas class="display-canvas" ref={canvas_ref}></canvas>
+    )
+}
+
+#[function_component(StatisticsPanel)]
+pub fn statistics_panel() -> Html {
+    let statistics = *use_selector(|s: &ImageState| s.statistics).as_ref();
+    let profile = use_selector(|s: &ImageState| s.profile.clone());
+
+    let truncated = match statistics.truncated {
+        Some(TruncationReason::MaxNodes) => "Stopped at the node limit",
+        Some(TruncationReason::MaxSteps) => "Stopped at the step limit",
+        Some(TruncationReason::MaxTime) => "Stopped at the time limit",
+        None => "",
+    };
+
+    let rows = profile.busiest().into_iter().map(|r| {
+        html!(
+            <tr>
+            <td>{r.name.clone()}</td>
+            <td>{r.invocations}</td>
+            <td>{r.cases_chosen.iter().join(" / ")}</td>
+            <td>{r.new_nodes}</td>
+            <td>{r.culled.transparent}</td>
+            <td>{r.culled.too_small}</td>
+            <td>{r.culled.too_deep}</td>
+            <td>{r.culled.out_of_bounds}</td>
+            <td>{r.max_depth}</td>
+            </tr>
+        )
+    });
+
+    html!(
+        <>
+        <code>{format!("{} nodes, {} culled. {}", statistics.new_nodes, statistics.nodes_culled, truncated)}</code>
+        <table>
+        <thead>
+        <tr>
+        <th>{"Rule"}</th>
+        <th>{"Invocations"}</th>
+        <th>{"Cases"}</th>
+        <th>{"Nodes"}</th>
+        <th>{"Culled (alpha)"}</th>
+        <th>{"Culled (size)"}</th>
+        <th>{"Culled (depth)"}</th>
+        <th>{"Culled (bounds)"}</th>
+        <th>{"Max Depth"}</th>
+        </tr>
+        </thead>
+        <tbody>
+        {for rows}
+        </tbody>
+        </table>
+        </>
+    )
+}
@@ -0,0 +1,87 @@
+use std::f64::consts::{FRAC_PI_2, PI, TAU};
+
+use crate::core::prelude::*;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{CanvasRenderingContext2d, HtmlCanvasElement};
+
+///Draw these shapes on a canvas, fitting the area of the frame inside it as svg does.
+///The canvas is resized to its displayed size so that it is sharp on high density screens.
+pub fn draw_shapes(
+    canvas: &HtmlCanvasElement,
+    frame: &Frame,
+    shapes: &[CanvasShape],
+) -> Result<(), JsValue> {
+    let ratio = web_sys::window()
+        .map(|w| w.device_pixel_ratio())
+        .unwrap_or(1.0);
+    let width = (canvas.client_width() as f64 * ratio).round().max(1.0);
+    let height = (canvas.client_height() as f64 * ratio).round().max(1.0);
+    //Resizing also clears the canvas
+    canvas.set_width(width as u32);
+    canvas.set_height(height as u32);
+
+    let context = canvas
+        .get_context("2d")?
+        .ok_or_else(|| JsValue::from_str("Canvas has no 2d context"))?
+        .dyn_into::<CanvasRenderingContext2d>()?;
+    let bounds = frame.bounds;
+    let scale = bounds.scale_to_fit(width as f32, height as f32) as f64;
+    let (centre_x, centre_y) = bounds.centre();
+    let offset_x = width / 2.0 - centre_x as f64 * scale;
+    let offset_y = height / 2.0 - centre_y as f64 * scale;
+
+    if let Some(background) = frame.background {
+        context.set_transform(scale, 0.0, 0.0, scale, offset_x, offset_y)?;
+        context.set_fill_style(&JsValue::from_str(&background.css()));
+        context.fill_rect(
+            bounds.min_x as f64,
+            bounds.min_y as f64,
+            bounds.width() as f64,
+            bounds.height() as f64,
+        );
+    }
+
+    for shape in shapes {
+        let [a, b, c, d, e, f] = shape.transform.map(|v| v as f64);
+        context.set_transform(
+            a * scale,
+            b * scale,
+            c * scale,
+            d * scale,
+            e * scale + offset_x,
+            f * scale + offset_y,
+        )?;
+        context.set_fill_style(&JsValue::from_str(&shape.color.css()));
+        context.begin_path();
+
+        match shape.primitive {
+            Primitive::Circle => context.ellipse(0.0, 0.0, 1.0, 1.0, 0.0, 0.0, TAU)?,
+            Primitive::Square if shape.corner.0 > 0.0 && shape.corner.1 > 0.0 => {
+                let (x, y) = (shape.corner.0 as f64, shape.corner.1 as f64);
+                //Each arc is joined to the end of the previous one by a straight line
+                context.ellipse(1.0 - x, y - 1.0, x, y, 0.0, -FRAC_PI_2, 0.0)?;
+                context.ellipse(1.0 - x, 1.0 - y, x, y, 0.0, 0.0, FRAC_PI_2)?;
+                context.ellipse(x - 1.0, 1.0 - y, x, y, 0.0, FRAC_PI_2, PI)?;
+                context.ellipse(x - 1.0, y - 1.0, x, y, 0.0, PI, PI + FRAC_PI_2)?;
+            }
+            Primitive::Square => context.rect(-1.0, -1.0, 2.0, 2.0),
+            Primitive::RightTriangle => {
+                context.move_to(0.0, -1.0);
+                context.line_to(1.0, 1.0);
+                context.line_to(-1.0, 1.0);
+            }
+            Primitive::Polygon(sides) => {
+                for (i, (x, y)) in Primitive::get_polygon_points(sides).enumerate() {
+                    if i == 0 {
+                        context.move_to(x as f64, y as f64);
+                    } else {
+                        context.line_to(x as f64, y as f64);
+                    }
+                }
+            }
+        }
+        context.close_path();
+        context.fill();
+    }
+    context.set_transform(1.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+}
@@ -1,6 +1,8 @@
 mod app;
+mod canvas;
 
 pub mod prelude {
 
     pub use crate::web::app::*;
+    pub use crate::web::canvas::*;
 }
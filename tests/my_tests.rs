@@ -1,7 +1,7 @@
 use convext::core::prelude::*;
 
 use ntest::test_case;
-use rand::SeedableRng;
+use rand::{Rng, SeedableRng};
 // use rand::{prelude::StdRng, Rng};
 
 pub const EXAMPLES: [&str; 8] = [
@@ -37,6 +37,40 @@ myshape p 0.75 h 10 a?alpha
 end",
 ];
 
+///Parse, compile and expand a grammar with the default settings
+fn expand(text: &str) -> (CompiledGrammar, NodeTree) {
+    let grammar = parse(text).unwrap().compile().unwrap();
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+    (grammar, tree)
+}
+
+///The centres of the leaves of a tree in tenths, in the order they are drawn
+fn leaf_centres(tree: &NodeTree) -> Vec<(f32, f32)> {
+    tree.leaves_in_draw_order()
+        .into_iter()
+        .map(|i| {
+            let properties = &tree.nodes[i].absolute_properties;
+            ((properties.x.value() * 10.0).round(), (properties.y.value() * 10.0).round())
+        })
+        .collect()
+}
+
+///Check that the transform of every child, relative to its parent, places it where its absolute properties do
+fn assert_transforms_match_positions(tree: &NodeTree) {
+    for parent in tree.nodes.iter() {
+        let (sin, cos) = parent.absolute_properties.r.value().to_radians().sin_cos();
+        let p = parent.absolute_properties.p.value();
+        for child in tree.children(parent) {
+            let t = child.transform;
+            let x = parent.absolute_properties.x.value() + p * (cos * t.x - sin * t.y);
+            let y = parent.absolute_properties.y.value() + p * (sin * t.x + cos * t.y);
+            assert!((x - child.absolute_properties.x.value()).abs() < 0.001);
+            assert!((y - child.absolute_properties.y.value()).abs() < 0.001);
+        }
+    }
+}
+
 #[test_case(0)]
 #[test_case(1)]
 #[test_case(2)]
@@ -47,14 +81,1094 @@ end",
 #[test_case(7)]
 fn test_svg(index: usize) {
     let input = EXAMPLES[index];
-    let grammar = parse(input).unwrap();
+    let grammar = parse(input).unwrap().compile().unwrap();
 
     let mut rng = SeedableRng::seed_from_u64(100);
 
-    let node = grammar.expand(&ExpandSettings::default(), &mut rng);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
 
-    let svg = node.to_svg(&grammar, &mut rng);
+    let svg = tree.to_svg(&grammar);
 
     assert!(!svg.is_empty());
     //print!("\r\n{svg}\r\n");
 }
+
+#[test_case(0)]
+#[test_case(1)]
+#[test_case(2)]
+#[test_case(3)]
+#[test_case(4)]
+#[test_case(5)]
+#[test_case(6)]
+#[test_case(7)]
+fn test_streaming_svg(index: usize) {
+    let input = EXAMPLES[index];
+    let grammar = parse(input).unwrap().compile().unwrap();
+    //The output is only the same when the budget does not run out
+    let settings = ExpandSettings {
+        max_nodes: 100_000,
+        ..Default::default()
+    };
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&settings, &mut rng);
+    assert!(tree.statistics.truncated.is_none());
+    let expected = tree.to_svg(&grammar);
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let mut actual = String::new();
+    grammar.write_svg(&settings, &mut rng, &mut actual).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[cfg(feature = "parallel")]
+#[test_case(5)]
+#[test_case(6)]
+#[test_case(7)]
+fn test_parallel_expansion_is_deterministic(index: usize) {
+    let input = EXAMPLES[index];
+    let grammar = parse(input).unwrap().compile().unwrap();
+    let settings = ExpandSettings::default();
+
+    let expand_with_threads = |threads: usize| {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        pool.install(|| {
+            let mut rng = SeedableRng::seed_from_u64(100);
+            let tree = grammar.expand(&settings, &mut rng);
+            tree.to_svg(&grammar)
+        })
+    };
+
+    let expected = expand_with_threads(1);
+    for threads in [2, 3, 8] {
+        assert_eq!(expected, expand_with_threads(threads));
+    }
+}
+
+#[test]
+fn test_expand_cached_reuses_unaffected_subtrees() {
+    let input = "let hue 100
+big
+small x 0.5
+rul big
+circle h ?hue
+big p 0.5
+end
+rul small
+square
+small p 0.5
+end";
+    let mut grammar = parse(input).unwrap().compile().unwrap();
+    let settings = ExpandSettings::default();
+    let mut cache = ExpansionCache::default();
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    grammar.expand_cached(&settings, &mut rng, &mut cache);
+    assert_eq!(cache.reused, 0);
+
+    grammar.set_variable("hue", 200.0);
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let cached = grammar.expand_cached(&settings, &mut rng, &mut cache).clone();
+    assert!(cache.reused > 0);
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let expected = grammar.expand(&settings, &mut rng);
+    assert!(expected == cached);
+}
+
+#[test]
+fn test_expand_cached_with_occlusion_culling() {
+    let mut grammar = parse("let hue 100\nsquare p 0.5 h ?hue\ncover\nrul cover\nsquare p 0.2\nsquare\nend")
+        .unwrap()
+        .compile()
+        .unwrap();
+    let settings = ExpandSettings {
+        occlusion_culling: true,
+        ..Default::default()
+    };
+    let mut cache = ExpansionCache::default();
+
+    for hue in [100.0, 200.0, 300.0] {
+        grammar.set_variable("hue", hue);
+        let mut rng = SeedableRng::seed_from_u64(100);
+        let cached = grammar.expand_cached(&settings, &mut rng, &mut cache).clone();
+        let mut rng = SeedableRng::seed_from_u64(100);
+        let expected = grammar.expand(&settings, &mut rng);
+        assert!(expected == cached);
+        assert_eq!(cached.statistics.culled.occluded, 2);
+    }
+    assert!(cache.reused > 0);
+}
+
+#[test]
+fn test_cached_expansion_reuses_subtrees_at_10k_nodes() {
+    //Only the top level circle reads the variable, so the whole tree can be reused
+    let mut grammar = parse(
+        "let hue 100
+circle p 0.1 h ?hue
+branch
+rul branch
+square p0.1 v0.5 a0.9
+branch p0.7 x0.5 y0.5 r45 h10
+branch p0.7 xsub0.5 y0.5 r sub 45 h10
+end",
+    )
+    .unwrap()
+    .compile()
+    .unwrap();
+    let settings = ExpandSettings {
+        max_nodes: 10_000,
+        max_depth: 100,
+        min_a: 0.0,
+        min_p: 0.0,
+        cull_margin: f32::INFINITY,
+        ..Default::default()
+    };
+    let mut cache = ExpansionCache::default();
+    let mut rng = SeedableRng::seed_from_u64(100);
+    grammar.expand_cached(&settings, &mut rng, &mut cache);
+
+    assert!(grammar.set_variable("hue", 101.0));
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand_cached(&settings, &mut rng, &mut cache);
+    assert_eq!(tree.nodes.len(), 10_000 + 3);
+    let expanded = tree.nodes.iter().skip(1).filter(|n| n.children.is_some()).count();
+
+    //Only the circle, and the node whose children were cut off by the budget, are expanded again.
+    //How much faster this is is measured by the `expand_cached_10k` bench.
+    assert_eq!(cache.reused, expanded - 2);
+}
+
+#[test_case(10)]
+#[test_case(100)]
+#[test_case(1000)]
+fn test_max_nodes_is_exact(max_nodes: usize) {
+    let grammar = parse(EXAMPLES[6]).unwrap().compile().unwrap();
+    let settings = ExpandSettings {
+        max_nodes,
+        ..Default::default()
+    };
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&settings, &mut rng);
+
+    assert_eq!(tree.statistics.new_nodes, max_nodes);
+    assert_eq!(tree.statistics.truncated, Some(TruncationReason::MaxNodes));
+}
+
+#[test]
+fn test_max_steps() {
+    let grammar = parse(EXAMPLES[6]).unwrap().compile().unwrap();
+    let settings = ExpandSettings {
+        max_steps: Some(10),
+        ..Default::default()
+    };
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&settings, &mut rng);
+
+    let expanded = tree.nodes.iter().skip(1).filter(|n| n.children.is_some()).count();
+    assert_eq!(expanded, 10);
+    assert_eq!(tree.statistics.truncated, Some(TruncationReason::MaxSteps));
+}
+
+#[test_case(0.5, 0)]
+#[test_case(20.0, 2)]
+fn test_out_of_bounds_subtrees_are_culled(cull_margin: f32, expected_children: usize) {
+    let grammar = parse(
+        "myshape x 10
+rul myshape
+circle
+myshape p 0.5 x 1
+end",
+    )
+    .unwrap()
+    .compile()
+    .unwrap();
+    let settings = ExpandSettings {
+        cull_margin,
+        max_nodes: 10,
+        ..Default::default()
+    };
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&settings, &mut rng);
+    let top_level = &tree.children(tree.root())[0];
+
+    assert_eq!(tree.children(top_level).len(), expected_children);
+    assert_eq!(tree.statistics.culled.out_of_bounds, 2 - expected_children);
+}
+
+#[test_case("circle\ncircle p 2", 1)]
+#[test_case("circle p 2\ncircle", 0)]
+#[test_case("circle\ncircle p 2 a 0.5", 0)]
+fn test_occlusion_culling(text: &str, expected_occluded: usize) {
+    let grammar = parse(text).unwrap().compile().unwrap();
+    let settings = ExpandSettings {
+        occlusion_culling: true,
+        ..Default::default()
+    };
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&settings, &mut rng);
+
+    assert_eq!(tree.statistics.culled.occluded, expected_occluded);
+    assert_eq!(tree.nodes.len(), 3 - expected_occluded);
+}
+
+#[test]
+fn test_profile() {
+    let grammar = parse(EXAMPLES[6]).unwrap().compile().unwrap();
+    let settings = ExpandSettings {
+        max_depth: 5,
+        ..Default::default()
+    };
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&settings, &mut rng);
+    let profile = tree.profile.busiest();
+
+    assert_eq!(tree.statistics.truncated, None);
+    assert_eq!(profile.len(), 2);
+    assert_eq!(profile.iter().map(|r| r.new_nodes).sum::<usize>(), tree.statistics.new_nodes);
+    for rule in profile {
+        assert_eq!(rule.cases_chosen, vec![rule.invocations]);
+        assert_eq!(rule.new_nodes + rule.culled.total(), rule.invocations * 3);
+    }
+}
+
+#[test]
+fn test_ranges_are_resolved_once() {
+    let grammar = parse(EXAMPLES[2]).unwrap().compile().unwrap();
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+
+    for node in tree.nodes.iter() {
+        assert!(matches!(node.absolute_properties.x, ValueOrRange::Value(_)));
+        assert!(matches!(node.absolute_properties.p, ValueOrRange::Value(_)));
+    }
+    assert_eq!(tree.to_svg(&grammar), tree.to_svg(&grammar));
+}
+
+#[test]
+fn test_interval_arithmetic() {
+    let range = |start, end| ValueOrRange::Range { start, end };
+
+    assert!(range(-1.0, 1.0) * range(-1.0, 1.0) == range(-1.0, 1.0));
+    assert!(range(1.0, 2.0) - range(0.0, 1.0) == range(0.0, 2.0));
+    assert!(UnaryOperator::Abs.apply_range(range(-2.0, 1.0)) == range(0.0, 2.0));
+    assert!(BinaryOperator::Lt.apply_range(range(0.0, 1.0), 2.0.into()) == 1.0.into());
+    assert!(
+        BinaryOperator::Lt.apply_range(range(0.0, 1.0), 0.5.into())
+            == ValueOrRange::Value(0.0).union(1.0.into())
+    );
+
+    let quotient = BinaryOperator::Div.apply_range(1.0.into(), range(-1.0, 1.0));
+    assert_eq!(quotient.intervals().len(), 2);
+    assert!(quotient.min_abs() == 1.0);
+}
+
+#[test_case(-2.0)]
+#[test_case(-0.0)]
+#[test_case(0.0)]
+#[test_case(0.5)]
+fn test_sig_of_a_range_starts_at_sig_of_its_start(value: f32) {
+    let sign = UnaryOperator::Sig.apply(value);
+    let range = UnaryOperator::Sig.apply_range(ValueOrRange::Range { start: value, end: 1.0 });
+
+    assert_eq!(range.min_value(), sign);
+    assert!(UnaryOperator::Sig.apply_range(value.into()) == sign.into());
+}
+
+#[test]
+fn test_compound_ranges_are_sampled_from_each_part() {
+    let grammar = parse("circle x 0..0.1, 0.9..1").unwrap().compile().unwrap();
+    let mut rng: rand::prelude::StdRng = SeedableRng::seed_from_u64(100);
+
+    let samples = (0..100)
+        .map(|_| grammar.expand(&ExpandSettings::default(), &mut rng).nodes[1].absolute_properties.x.value())
+        .collect::<Vec<_>>();
+
+    assert!(samples.iter().all(|x| (0.0..=0.1).contains(x) || (0.9..=1.0).contains(x)));
+    assert!(samples.iter().any(|x| *x < 0.5));
+    assert!(samples.iter().any(|x| *x > 0.5));
+}
+
+#[test]
+fn test_compound_ranges_merge_across_the_smallest_gap() {
+    let parts = [(0.0, 1.0), (1.5, 2.0), (5.0, 6.0), (9.0, 10.0)].map(|(a, b)| Interval::new(a, b));
+    let union = ValueOrRange::from_intervals(parts);
+
+    //The gap from 1 to 1.5 is the smallest, so it is filled in
+    assert_eq!(union.intervals(), vec![Interval::new(0.0, 2.0), parts[2], parts[3]]);
+    for part in parts {
+        assert!(union.intervals().iter().any(|i| i.contains(part.start) && i.contains(part.end)));
+    }
+
+    let mut rng: rand::prelude::StdRng = SeedableRng::seed_from_u64(100);
+    let samples = (0..1000).map(|_| union.random_value(&mut rng)).collect::<Vec<_>>();
+    assert!(samples.iter().all(|x| union.intervals().iter().any(|i| i.contains(*x))));
+    assert!(samples.iter().any(|x| (1.0..1.5).contains(x)));
+}
+
+#[test_case(0)]
+#[test_case(3)]
+#[test_case(6)]
+#[test_case(7)]
+fn test_svg_is_well_formed(index: usize) {
+    let grammar = parse(EXAMPLES[index]).unwrap().compile().unwrap();
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let svg = grammar.expand(&ExpandSettings::default(), &mut rng).to_svg(&grammar);
+    let document = roxmltree::Document::parse(&svg).unwrap();
+    let root = document.root_element();
+
+    assert_eq!(root.tag_name().namespace(), Some(SVG_NAMESPACE));
+    assert_eq!(root.tag_name().name(), "svg");
+    assert_eq!(root.attribute("viewBox"), Some("-1 -1 2 2"));
+    assert!(root.descendants().any(|n| n.attribute("fill").is_some()));
+}
+
+#[test]
+fn test_svg_attributes_are_escaped() {
+    let mut svg = SvgWriter::new(String::new());
+    svg.start_document(&Frame::default()).unwrap();
+    svg.empty_element("text", &[("class", &"a\"b'c<d>&e")]).unwrap();
+    svg.end_document().unwrap();
+    let svg = svg.into_inner();
+
+    let document = roxmltree::Document::parse(&svg).unwrap();
+    let text = document.descendants().find(|n| n.has_tag_name("text")).unwrap();
+    assert_eq!(text.attribute("class"), Some("a\"b'c<d>&e"));
+}
+
+#[test_case(5)]
+#[test_case(6)]
+#[test_case(7)]
+fn test_compact_svg(index: usize) {
+    let grammar = parse(EXAMPLES[index]).unwrap().compile().unwrap();
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+    let svg = tree.to_svg(&grammar);
+    let compact = tree.to_svg_with(&grammar, &SvgSettings::COMPACT);
+    assert!(compact.len() < svg.len());
+
+    let document = roxmltree::Document::parse(&compact).unwrap();
+    let ids = document
+        .descendants()
+        .filter_map(|n| n.attribute("id"))
+        .collect::<Vec<_>>();
+    for node in document.descendants().filter(|n| n.has_tag_name("use")) {
+        let href = node.attribute((XLINK_NAMESPACE, "href")).unwrap();
+        assert!(ids.contains(&&href[1..]));
+    }
+    let count = |svg: &str| {
+        roxmltree::Document::parse(svg)
+            .unwrap()
+            .descendants()
+            .filter(|n| n.has_tag_name("ellipse") || n.has_tag_name("rect") || n.has_tag_name("polygon"))
+            .count()
+    };
+    assert!(count(&compact) <= count(&svg));
+}
+
+#[test]
+fn test_svg_numbers_are_rounded() {
+    assert_eq!(SvgNumber(0.123456, Some(3)).to_string(), "0.123");
+    assert_eq!(SvgNumber(2.5, Some(3)).to_string(), "2.5");
+    assert_eq!(SvgNumber(1.0, Some(3)).to_string(), "1");
+    assert_eq!(SvgNumber(-0.0001, Some(3)).to_string(), "0");
+    assert_eq!(SvgNumber(0.125, None).to_string(), "0.125");
+}
+
+#[test]
+fn test_hsl_to_rgb() {
+    assert_eq!(Rgba::from_hsla(0.0, 1.0, 0.5, 1.0).hex(), "#ff0000");
+    assert_eq!(Rgba::from_hsla(120.0, 1.0, 0.5, 1.0).hex(), "#00ff00");
+    assert_eq!(Rgba::from_hsla(600.0, 1.0, 0.5, 1.0).hex(), "#0000ff");
+    assert_eq!(Rgba::from_hsla(30.0, 0.0, 0.5, 1.0).hex(), "#808080");
+    assert_eq!(Rgba::from_hsla(60.0, 1.0, 0.25, 1.0).hex(), "#808000");
+}
+
+#[test_case("circle", 0)]
+#[test_case("square r 45 c 0.2", 0)]
+#[test_case("hexagon p 0.8 x 0.5", 16)]
+fn test_raster(text: &str, centre_offset: u32) {
+    let grammar = parse(text).unwrap().compile().unwrap();
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+    let shape = &tree.nodes[1].absolute_properties;
+    let color = Rgba::from_properties(shape);
+
+    let image = tree.to_image(&grammar, 64, 64);
+    assert_eq!(image.pixel(32 + centre_offset, 32), [color.r, color.g, color.b, 255]);
+    assert_eq!(image.pixel(0, 0)[3], 0);
+
+    let total_alpha = image.pixels.chunks(4).map(|p| p[3] as f32 / 255.0).sum::<f32>();
+    let edges = image.pixels.chunks(4).filter(|p| p[3] > 0 && p[3] < 255).count();
+    assert!(total_alpha > 0.0 && edges > 0);
+
+    let png = tree.to_png(&grammar, 64, 64).unwrap();
+    assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+}
+
+#[test]
+fn test_raster_coverage() {
+    let grammar = parse("circle p 0.5").unwrap().compile().unwrap();
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+
+    let image = tree.to_image(&grammar, 100, 100);
+    let area = image.pixels.chunks(4).map(|p| p[3] as f32 / 255.0).sum::<f32>();
+    let expected = std::f32::consts::PI * 25.0 * 25.0;
+    assert!((area - expected).abs() < 2.0, "{area} {expected}");
+}
+
+#[test_case(3)]
+#[test_case(7)]
+fn test_pdf(index: usize) {
+    let grammar = parse(EXAMPLES[index]).unwrap().compile().unwrap();
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+
+    let pdf = tree.to_pdf(&grammar, &PdfSettings::A4);
+    let find = |pattern: &[u8]| pdf.windows(pattern.len()).rposition(|w| w == pattern).unwrap();
+    assert!(pdf.starts_with(b"%PDF-1.4"));
+    assert!(pdf.ends_with(b"%%EOF\n"));
+    find(b"/MediaBox [0 0 595.27");
+
+    //Every object is where the cross reference table says it is
+    let xref = find(b"xref\n0 ");
+    let trailer = String::from_utf8(pdf[xref..].to_vec()).unwrap();
+    assert!(trailer.ends_with(&format!("startxref\n{xref}\n%%EOF\n")));
+    for (i, line) in trailer.lines().skip(3).take(4).enumerate() {
+        let offset: usize = line[..10].parse().unwrap();
+        assert!(pdf[offset..].starts_with(format!("{} 0 obj", i + 1).as_bytes()));
+    }
+
+    let stream_start = pdf.windows(7).position(|w| w == b"stream\n").unwrap() + 7;
+    let stream_end = find(b"\nendstream");
+    let content = miniz_oxide::inflate::decompress_to_vec_zlib(&pdf[stream_start..stream_end]).unwrap();
+    let content = String::from_utf8(content).unwrap();
+    let shapes = tree
+        .leaves_in_draw_order()
+        .into_iter()
+        .filter(|i| matches!(grammar.get_invocation(tree.nodes[*i].invocation).method, CompiledMethod::Primitive(_)))
+        .count();
+    assert_eq!(content.matches("f Q").count(), shapes);
+    assert_eq!(content.contains(" gs "), index == 7);
+}
+
+#[test_case(PlotterFormat::Hpgl)]
+#[test_case(PlotterFormat::GCode)]
+#[test_case(PlotterFormat::Svg)]
+fn test_plotter(format: PlotterFormat) {
+    let grammar = parse(EXAMPLES[6]).unwrap().compile().unwrap();
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+    let settings = PlotterSettings {
+        hue_layers: 4,
+        optimise_travel: false,
+        ..Default::default()
+    };
+
+    let mut plot = tree.to_plot(&grammar, &settings);
+    assert_eq!(plot.layers.len(), 2);
+    for (x, y) in plot.layers.iter().flat_map(|l| l.paths.iter()).flat_map(|p| p.points.iter()) {
+        assert!(*x >= settings.margin && *x <= settings.page_width - settings.margin);
+        assert!(*y >= settings.margin && *y <= settings.page_height - settings.margin);
+    }
+
+    let travel = plot.travel_distance();
+    plot.optimise_travel();
+    assert!(plot.travel_distance() < travel);
+
+    let output = plot.export(format);
+    let pen_downs = plot.layers.iter().map(|l| l.paths.len()).sum::<usize>();
+    match format {
+        PlotterFormat::Hpgl => assert_eq!(output.matches("PD").count(), pen_downs),
+        PlotterFormat::GCode => assert_eq!(output.matches("G1 Z0").count(), pen_downs),
+        PlotterFormat::Svg => {
+            let document = roxmltree::Document::parse(&output).unwrap();
+            let layers = document
+                .descendants()
+                .filter(|n| n.attribute((INKSCAPE_NAMESPACE, "groupmode")) == Some("layer"))
+                .count();
+            assert_eq!(layers, 2);
+            assert_eq!(document.descendants().filter(|n| n.has_tag_name("path")).count(), pen_downs);
+        }
+    }
+}
+
+#[test]
+fn test_clipped_outlines_are_rejoined() {
+    let square = Polyline {
+        points: vec![(1.0, 1.0), (-1.0, 1.0), (-1.0, -1.0), (1.0, -1.0)],
+        closed: true,
+    };
+
+    let parts = clip_polyline(&square, (0.0, -5.0), (5.0, 5.0));
+
+    assert_eq!(parts.len(), 1);
+    assert_eq!(parts[0].points, vec![(0.0, -1.0), (1.0, -1.0), (1.0, 1.0), (0.0, 1.0)]);
+}
+
+#[test_case(1)]
+#[test_case(10)]
+#[test_case(2000)]
+fn test_optimise_travel_chooses_the_nearest_path(count: usize) {
+    let mut rng: rand::prelude::StdRng = SeedableRng::seed_from_u64(100);
+    let paths = (0..count)
+        .map(|i| {
+            let (x, y) = (rng.gen_range(10.0..200.0), rng.gen_range(10.0..280.0));
+            let mut points = vec![(x, y), (x + rng.gen_range(-5.0..5.0), y + rng.gen_range(-5.0..5.0))];
+            if i % 3 == 0 {
+                points.push((x, y + 1.0));
+            }
+            Polyline { points, closed: i % 3 == 0 }
+        })
+        .collect::<Vec<_>>();
+    let mut plot = Plot {
+        page_width: 210.0,
+        page_height: 297.0,
+        layers: vec![PlotterLayer { hue: 0.0, paths: paths.clone() }],
+    };
+
+    plot.optimise_travel();
+
+    //Each path starts at the nearest start, or end of an open path, to where the last one ended
+    let distance = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0).hypot(a.1 - b.1);
+    let mut remaining = paths;
+    let mut position = (0.0, 0.0);
+    for path in plot.layers[0].paths.iter() {
+        let nearest = remaining
+            .iter()
+            .flat_map(|p| [Some(p.start()), (!p.closed).then(|| p.end())])
+            .flatten()
+            .map(|end| distance(position, end))
+            .fold(f32::INFINITY, f32::min);
+        assert_eq!(distance(position, path.start()), nearest);
+        let index = remaining
+            .iter()
+            .position(|p| p.points == path.points || p.points.iter().rev().eq(path.points.iter()))
+            .unwrap();
+        remaining.swap_remove(index);
+        position = path.end();
+    }
+    assert!(remaining.is_empty());
+}
+
+#[test_case(0.0, 27)]
+#[test_case(0.5, 14)]
+#[test_case(0.75, 7)]
+#[test_case(1.0, 0)]
+fn test_hatching_follows_lightness(v: f32, expected_lines: usize) {
+    let grammar = parse(&format!("square p 0.1 v {v}")).unwrap().compile().unwrap();
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+    let settings = PlotterSettings {
+        hatch_spacing: Some(1.0),
+        ..Default::default()
+    };
+
+    let plot = tree.to_plot(&grammar, &settings);
+    let hatches = plot.layers[0].paths.iter().filter(|p| !p.closed).count();
+    assert_eq!(hatches, expected_lines);
+}
+
+#[test]
+fn test_dxf() {
+    let grammar = parse(
+        "circle p 0.2 xsub0.5
+        eye x0.5
+        square p 0.2 c 0.5 y0.5
+        rul eye
+        circle p 0.3
+        hexagon p 0.1
+        circle p 0.2 w 0.5
+        end",
+    )
+    .unwrap()
+    .compile()
+    .unwrap();
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+
+    let dxf = tree.to_dxf(&grammar, &DxfSettings::default());
+    //Every group is a code followed by its value
+    let lines = dxf.lines().map(|l| l.trim()).collect::<Vec<_>>();
+    let groups = lines.chunks(2).map(|g| (g[0], g[1])).collect::<Vec<_>>();
+    let values = |code: &str| {
+        groups
+            .iter()
+            .filter(|g| g.0 == code)
+            .map(|g| g.1)
+            .collect::<Vec<_>>()
+    };
+    //R12 headers have no `$INSUNITS` or `$MEASUREMENT`
+    assert_eq!(values("9"), vec!["$ACADVER"]);
+    let entities = values("0");
+    assert_eq!(entities.iter().filter(|e| **e == "CIRCLE").count(), 2);
+    assert_eq!(entities.iter().filter(|e| **e == "POLYLINE").count(), 3);
+    assert_eq!(entities.last(), Some(&"EOF"));
+
+    let mut layers = values("8");
+    layers.dedup();
+    assert_eq!(layers, vec!["0", "EYE", "0"]);
+    //The rounded square has an arc at each corner
+    assert_eq!(values("42").len(), 4);
+
+    let settings = DxfSettings {
+        layers: DxfLayers::Property {
+            key: PropertyKey::P,
+            step: 0.1,
+        },
+        ..Default::default()
+    };
+    let dxf = tree.to_dxf(&grammar, &settings);
+    assert!(dxf.contains("\nP_0_2\n"));
+    assert!(dxf.contains("\nP_0_3\n"));
+}
+
+#[test_case("square p 0.5", "", 2500.0)]
+#[test_case("square p 0.5 z 0.3", "?z mul 10", 7500.0)]
+#[test_case("circle p 0.5 z 0.3 v 1", "?z mul ?v mul 10", 5890.49)]
+#[test_case("circle p 0.5", "?z", 0.0)]
+fn test_mesh(text: &str, height: &str, expected_volume: f32) {
+    let grammar = parse(text).unwrap().compile().unwrap();
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+    let mut settings = MeshSettings::default();
+    if !height.is_empty() {
+        settings.height = MeshHeight::Expression(height.to_string());
+    }
+    let mesh = tree.to_mesh(&grammar, &settings).unwrap();
+
+    //Every edge is shared by two triangles which go along it in opposite directions
+    let mut edges = std::collections::BTreeMap::<(u32, u32), i32>::new();
+    for [a, b, c] in mesh.triangles.iter() {
+        for (start, end) in [(a, b), (b, c), (c, a)] {
+            *edges.entry((*start.min(end), *start.max(end))).or_default() += if start < end { 1 } else { -1 };
+        }
+    }
+    assert!(edges.values().all(|v| *v == 0));
+
+    //The signed volume of the tetrahedra from the origin to each triangle
+    let volume = mesh
+        .triangles
+        .iter()
+        .map(|t| {
+            let [a, b, c] = t.map(|i| mesh.vertices[i as usize]);
+            (a[0] * (b[1] * c[2] - b[2] * c[1]) - a[1] * (b[0] * c[2] - b[2] * c[0])
+                + a[2] * (b[0] * c[1] - b[1] * c[0]))
+                / 6.0
+        })
+        .sum::<f32>();
+    let plate = settings.size * settings.size * settings.base;
+    assert!((volume - plate - expected_volume).abs() < 1.0, "{volume}");
+
+    let stl = mesh.to_stl();
+    assert_eq!(stl.len(), 84 + 50 * mesh.triangles.len());
+    assert_eq!(u32::from_le_bytes(stl[80..84].try_into().unwrap()) as usize, mesh.triangles.len());
+
+    let obj = mesh.to_obj();
+    assert_eq!(obj.lines().filter(|l| l.starts_with("v ")).count(), mesh.vertices.len());
+    assert_eq!(obj.lines().filter(|l| l.starts_with("f ")).count(), mesh.triangles.len());
+}
+
+#[test]
+fn test_mesh_height_must_parse() {
+    let grammar = parse("square").unwrap().compile().unwrap();
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+    let settings = MeshSettings {
+        height: MeshHeight::Expression("?v mul".to_string()),
+        ..Default::default()
+    };
+    assert!(tree.to_mesh(&grammar, &settings).is_err());
+}
+
+#[test]
+fn test_scene_json() {
+    let grammar = parse(
+        "flower
+        rul flower
+        petal r 0
+        petal r 120
+        circle p 0.2 h 60 a 0.5
+        end
+        rul petal
+        hexagon p 0.3 y 0.5
+        end",
+    )
+    .unwrap()
+    .compile()
+    .unwrap();
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+
+    let json = tree.to_json(&grammar);
+    let scene = Scene::from_json(&json).unwrap();
+    assert_eq!(scene.version, SCENE_VERSION);
+    assert_eq!(scene.leaves.len(), 3);
+
+    let petal = &scene.leaves[1];
+    assert!(petal.primitive == Primitive::Polygon(6));
+    assert_eq!(petal.rule_path, vec!["flower", "petal"]);
+    assert_eq!(petal.depth, 3);
+    assert!((petal.transform.x - -0.5 * 120f32.to_radians().sin()).abs() < 0.001);
+    assert!((petal.transform.half_width - 0.3).abs() < 0.001);
+
+    let centre = &scene.leaves[2];
+    assert_eq!(centre.rule_path, vec!["flower"]);
+    assert_eq!(centre.color.hex(), Rgba::from_hsla(60.0, 1.0, 0.0, 0.5).hex());
+    assert_eq!(centre.color.a, 0.5);
+
+    let newer = json.replacen(&format!("\"version\": {}", SCENE_VERSION), "\"version\": 1000", 1);
+    assert!(Scene::from_json(&newer).is_err());
+}
+
+#[test]
+fn test_canvas_shapes_match_outlines() {
+    let grammar = parse(EXAMPLES[6]).unwrap().compile().unwrap();
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&ExpandSettings::default(), &mut rng);
+    let shapes = tree.to_canvas_shapes(&grammar);
+    let leaves = tree
+        .leaves_in_draw_order()
+        .into_iter()
+        .filter(|i| matches!(grammar.get_invocation(tree.nodes[*i].invocation).method, CompiledMethod::Primitive(_)))
+        .collect::<Vec<_>>();
+    assert_eq!(shapes.len(), leaves.len());
+
+    for (shape, index) in shapes.iter().zip(leaves) {
+        let node = &tree.nodes[index];
+        let [a, b, c, d, e, f] = shape.transform;
+        if shape.corner != (0.0, 0.0) {
+            continue;
+        }
+        //The third point of the outline of a square is its top left corner
+        let outline = Primitive::Square.outline(&node.absolute_properties, 0.001);
+        let expected = outline[2];
+        let (x, y) = (-a - c + e, -b - d + f);
+        assert!((x - expected.0).abs() < 0.0001 && (y - expected.1).abs() < 0.0001);
+        assert!(shape.color == Rgba::from_properties(&node.absolute_properties));
+    }
+}
+
+#[test]
+fn test_wide_squares_have_elliptical_corners() {
+    let (grammar, tree) = expand("square p 0.5 l 0.5 c 1");
+    let shapes = tree.to_canvas_shapes(&grammar);
+    //The corners are as wide and as tall as the square, as they are in svg
+    assert_eq!(shapes[0].corner, (1.0, 1.0));
+
+    let properties = &tree.nodes[tree.leaves_in_draw_order()[0]].absolute_properties;
+    let p = properties.p.value();
+    let (radius_x, radius_y) = (p * properties.w.value(), p * properties.l.value());
+    let (centre_x, centre_y) = (properties.x.value(), properties.y.value());
+    for (x, y) in Primitive::Square.outline(properties, 0.001) {
+        let distance = ((x - centre_x) / radius_x).powi(2) + ((y - centre_y) / radius_y).powi(2);
+        assert!((distance - 1.0).abs() < 0.01, "{distance}");
+    }
+}
+
+///Records what it is asked to draw
+#[derive(Default)]
+struct RecordingRenderer {
+    events: Vec<String>,
+    depth: usize,
+}
+
+impl Renderer for RecordingRenderer {
+    type Output = Vec<String>;
+
+    fn begin_group(&mut self, transform: &RelativeTransform) {
+        self.depth += 1;
+        self.events.push(format!("begin {:?}", transform));
+    }
+
+    fn end_group(&mut self) {
+        self.depth -= 1;
+        self.events.push("end".to_string());
+    }
+
+    fn draw_primitive(&mut self, shape: &RenderShape) {
+        self.events.push(format!(
+            "draw {:?} {:?} {:?}",
+            shape.transform, shape.color, shape.rule_path
+        ));
+    }
+
+    fn finish(self) -> Self::Output {
+        assert_eq!(self.depth, 0);
+        self.events
+    }
+}
+
+#[test_case(3)]
+#[test_case(6)]
+fn test_renderer(index: usize) {
+    let grammar = parse(EXAMPLES[index]).unwrap().compile().unwrap();
+    let settings = ExpandSettings {
+        max_nodes: 100_000,
+        ..Default::default()
+    };
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&settings, &mut rng);
+    assert!(tree.statistics.truncated.is_none());
+
+    let events = tree.render(&grammar, RecordingRenderer::default());
+    let draws = events.iter().filter(|e| e.starts_with("draw")).count();
+    assert_eq!(draws, tree.to_canvas_shapes(&grammar).len());
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let streamed = grammar.render_streaming(&settings, &mut rng, RecordingRenderer::default());
+    assert_eq!(events, streamed);
+}
+
+#[test_case(1000, 20)]
+#[test_case(100_000, 100)]
+fn test_streaming_spends_the_same_node_budget(max_nodes: usize, max_depth: usize) {
+    let grammar = parse("tri\nrul tri\ntriangle\ntri p 0.5 y 0.5\ntri p 0.5 x 0.5\ntri p 0.5 x sub 0.5\nend")
+        .unwrap()
+        .compile()
+        .unwrap();
+    let settings = ExpandSettings {
+        max_nodes,
+        max_depth,
+        min_p: 0.0,
+        ..Default::default()
+    };
+
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let tree = grammar.expand(&settings, &mut rng);
+    let mut rng = SeedableRng::seed_from_u64(100);
+    let (events, statistics) =
+        grammar.render_streaming_with_statistics(&settings, &mut rng, RecordingRenderer::default());
+
+    assert_eq!(tree.statistics.truncated, Some(TruncationReason::MaxNodes));
+    assert_eq!(statistics.truncated, Some(TruncationReason::MaxNodes));
+    assert_eq!(statistics.new_nodes, max_nodes);
+    assert_eq!(statistics.new_nodes, tree.statistics.new_nodes);
+    assert_eq!(tree.nodes.len(), 2 + max_nodes);
+    assert!(!events.is_empty());
+
+    //The budget is spent depth first, so the same number of nodes are made but they are not the same nodes
+    let expanded = tree.render(&grammar, RecordingRenderer::default());
+    let draws = |events: &[String]| events.iter().filter(|e| e.starts_with("draw")).count();
+    assert_eq!(draws(&events), draws(&expanded));
+    assert_ne!(events, expanded);
+    let deepest = |events: &[String]| {
+        events
+            .iter()
+            .scan(0, |depth, e| {
+                *depth += e.starts_with("begin") as usize;
+                *depth -= (e == "end") as usize;
+                Some(*depth)
+            })
+            .max()
+            .unwrap()
+    };
+    assert_eq!(deepest(&events), max_depth);
+    assert!(deepest(&expanded) < max_depth / 2);
+}
+
+#[test]
+fn test_frame_directives() {
+    let (mut grammar, tree) = expand("let wide 2\ncanvas w ?wide\nbackground v 1 a 0.5\ncircle p 0.5");
+    let svg = tree.to_svg(&grammar);
+    assert!(svg.contains("viewBox=\"-2 -1 4 2\""));
+    assert!(svg.contains("<rect x=\"-2\" y=\"-1\" width=\"4\" height=\"2\" fill=\"#ffffff\" fill-opacity=\"0.5\"/>"));
+    assert!(grammar.set_variable("wide", 3.0));
+    assert_eq!(grammar.frame.bounds, Bounds::new(-3.0, -1.0, 3.0, 1.0));
+
+    let image = tree.to_image(&grammar, 60, 20);
+    assert_eq!(image.pixel(1, 10), [255, 255, 255, 128]);
+
+    //Shapes outside the unit canvas are kept if they are inside the declared canvas
+    let (grammar, tree) = expand("canvas w 4\nfar\nrul far\ncircle x 3 p 0.1\nend");
+    assert!(tree.to_svg(&grammar).contains("<ellipse"));
+    let (grammar, tree) = expand("far\nrul far\ncircle x 3 p 0.1\nend");
+    assert!(!tree.to_svg(&grammar).contains("<ellipse"));
+
+    let (grammar, tree) = expand("canvas fit\ncircle x 3 p 0.5");
+    assert_eq!(tree.frame(&grammar).bounds, Bounds::new(2.5, -0.5, 3.5, 0.5));
+    assert!(tree.to_svg(&grammar).contains("viewBox=\"2.5 -0.5 1 1\""));
+
+    assert!(parse("canvas x ?y\ncircle").unwrap().compile().is_err());
+    assert!(parse("canvas\ncanvas\ncircle").is_err());
+}
+
+#[test]
+fn test_embedded_source_round_trips() {
+    let source = EmbeddedSource {
+        source: "/* \"a\" < 'b' & c */\nlet size 0.5\nsquare p ?size r 0..90?".to_string(),
+        seed: u64::MAX - 7,
+        settings: ExpandSettings {
+            max_nodes: 500,
+            ..Default::default()
+        },
+        overrides: [("size".to_string(), 0.25)].into_iter().collect(),
+    };
+    let (grammar, tree) = source.draw().unwrap();
+    let svg = tree.to_svg(&grammar);
+
+    let embedded = source.embed_in_svg(&svg);
+    assert!(roxmltree::Document::parse(&embedded).is_ok());
+    let imported = EmbeddedSource::import(embedded.as_bytes()).unwrap();
+    assert!(imported == source);
+    let (grammar, tree) = imported.draw().unwrap();
+    assert_eq!(tree.to_svg(&grammar), svg);
+
+    let png = source.embed_in_png(&tree.to_image(&grammar, 16, 16)).unwrap();
+    assert!(EmbeddedSource::import(&png).unwrap() == source);
+
+    assert!(EmbeddedSource::from_svg(&svg).is_err());
+    assert!(EmbeddedSource::import(&tree.to_png(&grammar, 16, 16).unwrap()).is_err());
+}
+
+#[test]
+fn test_tiling_wraps_shapes_around_the_canvas() {
+    let (grammar, tree) = expand(
+        "thing p 0.5 r 90
+        rul thing
+        circle p 0.4 x 1.8
+        circle p 0.4 x 1.8 y 1.8
+        square p 0.1
+        end",
+    );
+    let tiled = tree.tiled(&grammar);
+
+    assert_eq!(
+        leaf_centres(&tiled),
+        vec![
+            (0.0, 9.0),
+            (0.0, -11.0),
+            (-9.0, 9.0),
+            (-9.0, -11.0),
+            (11.0, -11.0),
+            (11.0, 9.0),
+            (0.0, 0.0)
+        ]
+    );
+    //Copies are placed inside their groups as well as on the canvas
+    assert_transforms_match_positions(&tiled);
+
+    let svg = tree.to_tile_preview_svg(&grammar);
+    let document = roxmltree::Document::parse(&svg).unwrap();
+    assert_eq!(document.root_element().attribute("viewBox"), Some("-3 -3 6 6"));
+    assert_eq!(document.descendants().filter(|n| n.has_tag_name("use")).count(), 9);
+    assert_eq!(document.descendants().filter(|n| n.has_tag_name("ellipse")).count(), 6);
+}
+
+#[test]
+fn test_symmetry_directives() {
+    let (_, tree) = expand("circle p 0.1 x 0.5 sym rot 4");
+    assert_eq!(
+        leaf_centres(&tree),
+        vec![(5.0, 0.0), (0.0, 5.0), (-5.0, 0.0), (0.0, -5.0)]
+    );
+    let (_, tree) = expand("circle p 0.1 x 0.5 y 0.2 sym dih 6");
+    assert_eq!(tree.root().children.clone().unwrap().len(), 12);
+
+    //Random choices are made once, so every copy is the same distance from the centre
+    let (_, tree) = expand("circle p 0.1 x 0.1..0.9? sym rot 5");
+    let distances = tree
+        .children(tree.root())
+        .iter()
+        .map(|n| n.absolute_properties.x.value().hypot(n.absolute_properties.y.value()))
+        .collect::<Vec<_>>();
+    assert_eq!(distances.len(), 5);
+    assert!(distances.iter().all(|d| (d - distances[0]).abs() < 0.001));
+
+    //A mirrored subtree is reflected all the way down, and its groups place it without a reflection
+    let (grammar, tree) = expand("sym mirror\nside x 0.5 r 30\nrul side\ncircle p 0.1 x 0.5 y 0.2 r 20\nend");
+    let leaves = tree
+        .leaves_in_draw_order()
+        .into_iter()
+        .map(|i| &tree.nodes[i].absolute_properties)
+        .collect::<Vec<_>>();
+    assert_eq!(leaves.len(), 2);
+    assert!((leaves[0].x.value() + leaves[1].x.value()).abs() < 0.001);
+    assert!((leaves[0].y.value() - leaves[1].y.value()).abs() < 0.001);
+    assert!((leaves[0].r.value() - 50.0).abs() < 0.001);
+    assert!((leaves[1].r.value() - 310.0).abs() < 0.001);
+    assert!(leaves[1].mirrored);
+    assert_transforms_match_positions(&tree);
+    assert!(!tree.to_svg(&grammar).contains("scale(-1"));
+
+    //Wallpaper covers the canvas, and the rules using it are never culled for being out of bounds
+    let (grammar, tree) = expand("pattern\nrul pattern\ncircle p 0.05 x 0.1 y 0.1 sym p4m 0.5\nend");
+    assert!(grammar.rule_extents[0].is_infinite());
+    let centres = leaf_centres(&tree);
+    assert_eq!(centres.len() % 8, 0);
+    for corner in [(-9.0, -9.0), (9.0, -9.0), (-9.0, 9.0), (9.0, 9.0)] {
+        assert!(centres.contains(&corner));
+    }
+    let (_, tree) = expand("sym p6 0.5\ncircle p 0.05 sym mirror");
+    assert_eq!(leaf_centres(&tree).len() % 12, 0);
+
+    assert!(parse("circle sym rot 0").is_err());
+    assert!(parse("circle sym rot 2.5").is_err());
+    assert!(parse("circle sym p7").is_err());
+    assert!(parse("circle sym p4 0").is_err());
+    assert!(parse("sym mirror\nsym p1\ncircle").is_err());
+    assert!(parse("symbol\nrul symbol\ncircle\nend").is_ok());
+}
+
+#[test_case("p1", 1)]
+#[test_case("p2", 2)]
+#[test_case("pm", 2)]
+#[test_case("pg", 2)]
+#[test_case("cm", 4)]
+#[test_case("pmm", 4)]
+#[test_case("pmg", 4)]
+#[test_case("pgg", 4)]
+#[test_case("cmm", 8)]
+#[test_case("p4", 4)]
+#[test_case("p4m", 8)]
+#[test_case("p4g", 8)]
+#[test_case("p3", 3)]
+#[test_case("p3m1", 6)]
+#[test_case("p31m", 6)]
+#[test_case("p6", 6)]
+#[test_case("p6m", 12)]
+fn test_wallpaper_groups_are_closed(name: &str, expected_operations: usize) {
+    let group: WallpaperGroup = name.parse().unwrap();
+    let cell = 0.5;
+    let operations = group.cell_operations(cell);
+    assert_eq!(operations.len(), expected_operations);
+
+    //Two operations are the same if they differ only by a translation of the lattice
+    let [a, b] = group.lattice(cell);
+    let is_whole = |v: f32| (v - v.round()).abs() < 0.001;
+    let same = |l: &Isometry, r: &Isometry| {
+        let (dx, dy) = (l.x - r.x, l.y - r.y);
+        let j = dy / b.1;
+        let i = (dx - j * b.0) / a.0;
+        l.mirror == r.mirror && is_whole((l.r - r.r) / 360.0) && is_whole(i) && is_whole(j)
+    };
+
+    for (l, r) in operations.iter().flat_map(|l| operations.iter().map(move |r| (l, r))) {
+        //Doing `r` and then `l`
+        let (sin, cos) = l.r.to_radians().sin_cos();
+        let x = if l.mirror { -r.x } else { r.x };
+        let product = Isometry {
+            r: l.r + if l.mirror { -r.r } else { r.r },
+            mirror: l.mirror ^ r.mirror,
+            x: cos * x - sin * r.y + l.x,
+            y: sin * x + cos * r.y + l.y,
+        };
+        assert!(operations.iter().any(|o| same(o, &product)), "{name}: {l:?} after {r:?}");
+    }
+    for (i, l) in operations.iter().enumerate() {
+        assert!(operations[i + 1..].iter().all(|r| !same(l, r)), "{name}: {l:?} is repeated");
+    }
+}